@@ -0,0 +1,694 @@
+use crate::collections::{self, Collection};
+use crate::config::Config;
+use crate::documents::fetch_document;
+use crate::resilience::Guard;
+use crate::{PDFdoc, meilisearch_error};
+use actix_web::http::header;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use log::{error, info, warn};
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::task_info::TaskInfo;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sqlx::{MySql, MySqlPool, Transaction};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Returns the collection admins correct and re-index: the default one, holding the SIGRH
+/// documents mirrored by the DOCUMENT table.
+pub fn admin_collection(config: &Config) -> Result<Collection<'_>, Error> {
+    collections::resolve_one(config, None)
+}
+
+/// Returns the name of the admin making the request, identified by the bearer token of its
+/// Authorization header. Fails with an unauthorized error if the token is missing or unknown.
+pub fn authenticate(req: &HttpRequest, config: &Config) -> Result<String, Error> {
+    bearer_identity(req, &config.admin.tokens, "Invalid admin token")
+}
+
+/// Returns the name the bearer token of the Authorization header is mapped to in `tokens`. Fails
+/// with an unauthorized error carrying `message` if the token is missing or unknown.
+pub fn bearer_identity(
+    req: &HttpRequest,
+    tokens: &HashMap<String, String>,
+    message: &'static str,
+) -> Result<String, Error> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| tokens.get(token.trim()))
+        .cloned()
+        .ok_or_else(|| {
+            actix_web::error::InternalError::from_response(
+                message,
+                HttpResponse::Unauthorized()
+                    .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                    .body(message),
+            )
+            .into()
+        })
+}
+
+/// Fields of a document that can be corrected. Fields left out are not changed.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentPatch {
+    pub title: Option<String>,
+    pub date: Option<i64>,
+    pub is_normative: Option<i32>,
+    pub link: Option<String>,
+}
+
+/// A value of a document field before and after a change.
+#[derive(Serialize)]
+struct Change {
+    from: Value,
+    to: Value,
+}
+
+/// A line of the audit trail.
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: String,
+    admin: &'a str,
+    action: &'static str,
+    document: &'a str,
+    changes: BTreeMap<&'static str, Change>,
+}
+
+/// Serializes appends to the audit trail, so that concurrent changes cannot interleave their lines.
+static AUDIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Appends an entry to the audit trail. The change has already been made when this is called, so a
+/// failure to write it is logged rather than reported to the admin.
+fn audit(config: &Config, admin: &str, action: &'static str, document: &str, changes: BTreeMap<&'static str, Change>) {
+    let entry = AuditEntry {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        admin,
+        action,
+        document,
+        changes,
+    };
+    let mut line = match serde_json::to_string(&entry) {
+        Ok(line) => line,
+        Err(e) => {
+            error!("Could not serialize the audit entry of {document}: {e}");
+            return;
+        },
+    };
+    line.push('\n');
+
+    let _guard = AUDIT_LOCK.lock();
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.admin.audit_log)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = written {
+        error!(
+            "Could not write to the audit trail {:?}: {e}. Lost entry: {line}",
+            config.admin.audit_log
+        );
+    }
+}
+
+/// How long a change that was still pending when the admin was answered keeps being followed.
+const TASK_FOLLOW_LIMIT: Duration = Duration::from_secs(3600);
+
+/// Outcome of a Meilisearch task, as far as it is known.
+enum TaskOutcome {
+    Succeeded,
+    Failed(Error),
+    /// The task was not processed in time, or its state could not be read.
+    Pending,
+}
+
+/// Waits up to `wait` for a Meilisearch task to be processed.
+async fn wait_for_task(task: TaskInfo, client: &Client, wait: Duration) -> TaskOutcome {
+    let uid = task.task_uid;
+    match task.wait_for_completion(client, None, Some(wait)).await {
+        Ok(task) if task.is_failure() => TaskOutcome::Failed(meilisearch_error(
+            meilisearch_sdk::errors::Error::Meilisearch(task.unwrap_failure()),
+        )),
+        Ok(_) => TaskOutcome::Succeeded,
+        Err(meilisearch_sdk::errors::Error::Timeout) => TaskOutcome::Pending,
+        Err(e) => {
+            warn!("Could not follow task {uid}: {e:?}");
+            TaskOutcome::Pending
+        },
+    }
+}
+
+/// Whether a change was applied by the time the admin was answered.
+pub enum Settled {
+    Applied,
+    /// Meilisearch has not processed the task yet, the change is settled once it has.
+    Pending {
+        task: u32,
+    },
+}
+
+/// Settles a change sent to the index as `task` once the task has an outcome: if it succeeded,
+/// the database transaction is committed and `applied` is called, if it failed, the transaction is
+/// rolled back. Waits for the outcome up to the configured time, then answers that the change is
+/// pending and keeps following the task in the background, so that the transaction is never
+/// settled before Meilisearch has.
+async fn settle(
+    task: TaskInfo,
+    transaction: Option<Transaction<'static, MySql>>,
+    client: &Client,
+    index: &str,
+    config: &Config,
+    original: PDFdoc,
+    applied: impl FnOnce() + 'static,
+) -> Result<Settled, Error> {
+    let uid = task.task_uid;
+    let wait = Duration::from_millis(config.admin.task_wait_ms);
+    match wait_for_task(task.clone(), client, wait).await {
+        TaskOutcome::Succeeded => {
+            commit(transaction, client, index, &original).await?;
+            applied();
+            Ok(Settled::Applied)
+        },
+        TaskOutcome::Failed(e) => Err(e),
+        TaskOutcome::Pending => {
+            info!("Task {uid} is still pending, the change is settled once it is processed.");
+            let (client, index) = (client.clone(), index.to_string());
+            actix_rt::spawn(async move {
+                match wait_for_task(task, &client, TASK_FOLLOW_LIMIT).await {
+                    TaskOutcome::Succeeded => match commit(transaction, &client, &index, &original).await {
+                        Ok(()) => applied(),
+                        Err(e) => error!("Could not commit the change of task {uid}: {e}"),
+                    },
+                    TaskOutcome::Failed(e) => error!("Task {uid} failed, its change was rolled back: {e}"),
+                    TaskOutcome::Pending => error!(
+                        "Gave up on task {uid} after {TASK_FOLLOW_LIMIT:?}, its database change was rolled back and \
+                         may disagree with the index."
+                    ),
+                }
+            });
+            Ok(Settled::Pending { task: uid })
+        },
+    }
+}
+
+/// Logs a database error and turns it into an internal server error for the client.
+fn database_error(e: sqlx::Error) -> Error {
+    error!("Database Error: {e:?}");
+    actix_web::error::ErrorInternalServerError("Database update failed")
+}
+
+/// Starts a transaction on the MySQL database, if one is configured.
+async fn begin(database: &Option<MySqlPool>) -> Result<Option<Transaction<'static, MySql>>, Error> {
+    match database {
+        Some(pool) => pool.begin().await.map(Some).map_err(database_error),
+        None => Ok(None),
+    }
+}
+
+/// Commits the database transaction once Meilisearch has been updated. If the commit fails, the
+/// index is restored to the original document so that both stay in agreement.
+async fn commit(
+    transaction: Option<Transaction<'static, MySql>>,
+    client: &Client,
+    index: &str,
+    original: &PDFdoc,
+) -> Result<(), Error> {
+    let Some(transaction) = transaction else {
+        return Ok(());
+    };
+    if let Err(e) = transaction.commit().await {
+        if let Err(e) = client.index(index).add_or_update(&[original], Some("id")).await {
+            error!("Could not restore document {} in Meilisearch: {e:?}", original.id);
+        }
+        return Err(database_error(e));
+    }
+    Ok(())
+}
+
+/// Corrects the title, date, category or link of a document in Meilisearch and in the DOCUMENT
+/// table. Both are updated together: the database change is only committed once Meilisearch has
+/// applied its own, see [`settle`]. Returns the corrected document, or answers with a 202 and the
+/// uid of the Meilisearch task if it is still pending.
+pub async fn update_document(
+    req: HttpRequest,
+    path: web::Path<String>,
+    patch: web::Json<DocumentPatch>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    database: web::Data<Option<MySqlPool>>,
+) -> Result<HttpResponse, Error> {
+    let admin = authenticate(&req, &config)?;
+    let (document, settled) = patch_document(
+        &admin,
+        &path.into_inner(),
+        patch.into_inner(),
+        &client,
+        &guard,
+        &config,
+        &database,
+    )
+    .await?;
+
+    Ok(match settled {
+        Settled::Applied => HttpResponse::Ok().json(document),
+        Settled::Pending { task } => HttpResponse::Accepted().json(json!({ "task": task })),
+    })
+}
+
+/// Applies a correction made by an admin, as described in [`update_document`], and records it in
+/// the audit trail once it is applied. Returns the corrected document and whether it was applied.
+pub async fn patch_document(
+    admin: &str,
+    id: &str,
+    patch: DocumentPatch,
+    client: &Client,
+    guard: &Guard,
+    config: &Config,
+    database: &Option<MySqlPool>,
+) -> Result<(PDFdoc, Settled), Error> {
+    if patch.title.as_ref().is_some_and(|title| title.trim().is_empty()) {
+        return Err(actix_web::error::ErrorBadRequest("The title cannot be empty"));
+    }
+    if patch.is_normative.is_some_and(|category| !(1..=3).contains(&category)) {
+        return Err(actix_web::error::ErrorBadRequest("The category must be 1, 2 or 3"));
+    }
+
+    let collection = admin_collection(config)?;
+    let original = fetch_document(client, guard, &collection, id).await?;
+    let mut document = PDFdoc {
+        id: original.id.clone(),
+        title: patch.title.unwrap_or_else(|| original.title.clone()),
+        date: patch.date.unwrap_or(original.date),
+        content: original.content.clone(),
+        link: patch.link.unwrap_or_else(|| original.link.clone()),
+        is_normative: patch.is_normative.unwrap_or(original.is_normative),
+        collection: None,
+    };
+    document.title = document.title.trim().to_string();
+
+    let mut changes = BTreeMap::new();
+    let mut record = |field: &'static str, from: Value, to: Value| {
+        if from != to {
+            changes.insert(field, Change { from, to });
+        }
+    };
+    record("title", json!(original.title), json!(document.title));
+    record("date", json!(original.date), json!(document.date));
+    record(
+        "is_normative",
+        json!(original.is_normative),
+        json!(document.is_normative),
+    );
+    record("link", json!(original.link), json!(document.link));
+    if changes.is_empty() {
+        return Ok((document, Settled::Applied));
+    }
+
+    let mut transaction = begin(database).await?;
+    if let Some(transaction) = transaction.as_mut() {
+        let updated = sqlx::query("UPDATE DOCUMENT SET docName = ?, creationDate = ?, link = ? WHERE docKey = ?")
+            .bind(&document.title)
+            .bind(document.date)
+            .bind(&document.link)
+            .bind(&document.id)
+            .execute(&mut **transaction)
+            .await
+            .map_err(database_error)?;
+        if updated.rows_affected() == 0 {
+            warn!("Document {id} is not in the DOCUMENT table, only Meilisearch is updated.");
+        }
+    }
+
+    let task = client
+        .index(collection.index)
+        .add_or_update(&[&document], Some("id"))
+        .await
+        .map_err(meilisearch_error)?;
+    let (audited, admin, id) = (config.clone(), admin.to_string(), id.to_string());
+    let settled = settle(
+        task,
+        transaction,
+        client,
+        collection.index,
+        config,
+        original,
+        move || {
+            info!("{admin} updated document {id}.");
+            audit(&audited, &admin, "update", &id, changes);
+        },
+    )
+    .await?;
+
+    Ok((document, settled))
+}
+
+/// Removes a document from Meilisearch and from the DOCUMENT table, along with the favorites
+/// pointing to it. As with updates, the database change is only committed once Meilisearch has
+/// removed the document, and the admin is answered with a 202 if it has not yet.
+pub async fn delete_document(
+    req: HttpRequest,
+    path: web::Path<String>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    database: web::Data<Option<MySqlPool>>,
+) -> Result<HttpResponse, Error> {
+    let admin = authenticate(&req, &config)?;
+    let id = path.into_inner();
+    let collection = admin_collection(&config)?;
+    let original = fetch_document(&client, &guard, &collection, &id).await?;
+
+    let mut transaction = begin(&database).await?;
+    if let Some(transaction) = transaction.as_mut() {
+        sqlx::query("DELETE FROM favorites WHERE documentId = ?")
+            .bind(&id)
+            .execute(&mut **transaction)
+            .await
+            .map_err(database_error)?;
+        let deleted = sqlx::query("DELETE FROM DOCUMENT WHERE docKey = ?")
+            .bind(&id)
+            .execute(&mut **transaction)
+            .await
+            .map_err(database_error)?;
+        if deleted.rows_affected() == 0 {
+            warn!("Document {id} is not in the DOCUMENT table, only Meilisearch is updated.");
+        }
+    }
+
+    let task = client
+        .index(collection.index)
+        .delete_document(&id)
+        .await
+        .map_err(meilisearch_error)?;
+    let changes = BTreeMap::from([
+        (
+            "title",
+            Change {
+                from: json!(original.title),
+                to: Value::Null,
+            },
+        ),
+        (
+            "date",
+            Change {
+                from: json!(original.date),
+                to: Value::Null,
+            },
+        ),
+        (
+            "is_normative",
+            Change {
+                from: json!(original.is_normative),
+                to: Value::Null,
+            },
+        ),
+        (
+            "link",
+            Change {
+                from: json!(original.link),
+                to: Value::Null,
+            },
+        ),
+    ]);
+    let audited = config.get_ref().clone();
+    let settled = settle(
+        task,
+        transaction,
+        &client,
+        collection.index,
+        &config,
+        original,
+        move || {
+            info!("{admin} deleted document {id}.");
+            audit(&audited, &admin, "delete", &id, changes);
+        },
+    )
+    .await?;
+
+    Ok(match settled {
+        Settled::Applied => HttpResponse::NoContent().finish(),
+        Settled::Pending { task } => HttpResponse::Accepted().json(json!({ "task": task })),
+    })
+}
+
+/// Document entries as written by Document_Parser to `entries.json`.
+#[derive(Deserialize)]
+struct ParsedEntries {
+    entries: Vec<ParsedEntry>,
+}
+
+#[derive(Deserialize)]
+struct ParsedEntry {
+    id: String,
+    title: Option<String>,
+    date: Option<i64>,
+    content: String,
+    link: String,
+    is_normative: i32,
+}
+
+/// Loads every entry of the `entries.json` file written by Document_Parser into the index of the
+/// default collection,
+/// replacing the indexed documents with the same ids. Returns once Meilisearch has enqueued the
+/// task, which can be followed on the dashboard.
+pub async fn reindex(admin: &str, client: &Client, config: &Config) -> Result<TaskInfo, Error> {
+    let collection = admin_collection(config)?;
+    let path = &config.admin.entries_file;
+    let data = std::fs::read_to_string(path).map_err(|e| {
+        error!("Could not read {path:?}: {e}");
+        actix_web::error::ErrorInternalServerError("Could not read the parsed entries")
+    })?;
+    let parsed: ParsedEntries = serde_json::from_str(&data).map_err(|e| {
+        error!("Could not parse {path:?}: {e}");
+        actix_web::error::ErrorInternalServerError("Could not parse the parsed entries")
+    })?;
+
+    let documents: Vec<PDFdoc> = parsed
+        .entries
+        .into_iter()
+        .map(|entry| PDFdoc {
+            id: entry.id,
+            title: entry.title.unwrap_or_default(),
+            date: entry.date.unwrap_or_default(),
+            content: entry.content,
+            link: entry.link,
+            is_normative: entry.is_normative,
+            collection: None,
+        })
+        .collect();
+    let task = client
+        .index(collection.index)
+        .add_or_replace(&documents, Some("id"))
+        .await
+        .map_err(meilisearch_error)?;
+
+    info!(
+        "{admin} started re-indexing {} documents from {path:?} (task {}).",
+        documents.len(),
+        task.task_uid
+    );
+    audit(config, admin, "reindex", collection.index, BTreeMap::new());
+
+    Ok(task)
+}
+
+/// Starts loading the entries parsed by Document_Parser into the index, see [`reindex`]. Answers
+/// with the uid of the Meilisearch task.
+pub async fn reindex_documents(
+    req: HttpRequest,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let admin = authenticate(&req, &config)?;
+    let task = reindex(&admin, &client, &config).await?;
+
+    Ok(HttpResponse::Accepted().json(json!({ "task": task.task_uid })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Admin, Config};
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::http::{StatusCode, header};
+    use actix_web::test::{TestRequest, call_service, read_body_json};
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Id of the fixture `Resolução CAD nº 45/2020 - Progressão de carreira docente`.
+    const DOCUMENT_ID: &str = "ba31fcd0803ec0051801a25f8be93868b4af2de850fee27ae1e036be2351fab9";
+
+    /// Returns a configuration accepting the token `secret` for the admin `ana`, with an audit
+    /// trail of its own.
+    fn config(name: &str) -> (Config, PathBuf) {
+        let audit_log = std::env::temp_dir().join(format!("admin-audit-{name}-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&audit_log);
+        let config = Config {
+            admin: Admin {
+                tokens: HashMap::from([("secret".to_string(), "ana".to_string())]),
+                audit_log: audit_log.clone(),
+                ..Admin::default()
+            },
+            ..Config::default()
+        };
+        (config, audit_log)
+    }
+
+    fn authorized(request: TestRequest, token: &str) -> TestRequest {
+        request.insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+    }
+
+    /// Returns the lines of the audit trail.
+    fn audit_entries(audit_log: &PathBuf) -> Vec<Value> {
+        fs::read_to_string(audit_log)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[actix_rt::test]
+    async fn admins_correct_documents() {
+        let mock = MockMeilisearch::start();
+        let (config, audit_log) = config("update");
+        let app = test_app!(mock, config);
+        let uri = format!("/admin/documents/{DOCUMENT_ID}");
+
+        let request = TestRequest::patch()
+            .uri(&uri)
+            .set_json(json!({ "title": "  Resolução CAD nº 45/2020  ", "is_normative": 2 }));
+        let response = call_service(&app, authorized(request, "secret").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let document: Value = read_body_json(response).await;
+        assert_eq!(document["title"], "Resolução CAD nº 45/2020");
+        assert_eq!(document["is_normative"], 2);
+        assert_eq!(document["date"], 1590969600);
+
+        // The correction reached Meilisearch
+        let response = call_service(
+            &app,
+            TestRequest::get()
+                .uri(&format!("/documents/{DOCUMENT_ID}/view"))
+                .to_request(),
+        )
+        .await;
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<h1>Resolução CAD nº 45/2020</h1>"));
+
+        let entries = audit_entries(&audit_log);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["admin"], "ana");
+        assert_eq!(entries[0]["action"], "update");
+        assert_eq!(entries[0]["document"], DOCUMENT_ID);
+        assert_eq!(
+            entries[0]["changes"],
+            json!({
+                "title": {
+                    "from": "Resolução CAD nº 45/2020 - Progressão de carreira docente",
+                    "to": "Resolução CAD nº 45/2020",
+                },
+                "is_normative": { "from": 1, "to": 2 },
+            })
+        );
+
+        // The versioned route is the same endpoint
+        let request = TestRequest::patch()
+            .uri(&format!("/api/v1{uri}"))
+            .set_json(json!({ "is_normative": 4 }));
+        let response = call_service(&app, authorized(request, "secret").to_request()).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        fs::remove_file(audit_log).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn admins_delete_documents() {
+        let mock = MockMeilisearch::start();
+        let (config, audit_log) = config("delete");
+        let app = test_app!(mock, config);
+        let uri = format!("/admin/documents/{DOCUMENT_ID}");
+
+        let response = call_service(&app, authorized(TestRequest::delete().uri(&uri), "secret").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = call_service(&app, authorized(TestRequest::delete().uri(&uri), "secret").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let entries = audit_entries(&audit_log);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["action"], "delete");
+        assert_eq!(entries[0]["changes"]["title"]["to"], Value::Null);
+        fs::remove_file(audit_log).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn changes_pending_in_meilisearch_are_settled_later() {
+        let mock = MockMeilisearch::start();
+        let (mut config, audit_log) = config("pending");
+        config.admin.task_wait_ms = 200;
+        let app = test_app!(mock, config);
+
+        mock.hold_tasks(true);
+        let request = TestRequest::patch()
+            .uri(&format!("/admin/documents/{DOCUMENT_ID}"))
+            .set_json(json!({ "is_normative": 2 }));
+        let response = call_service(&app, authorized(request, "secret").to_request()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: Value = read_body_json(response).await;
+        assert!(body["task"].is_u64());
+        assert!(audit_entries(&audit_log).is_empty());
+
+        // The change is only recorded once Meilisearch has processed it
+        mock.hold_tasks(false);
+        for _ in 0..50 {
+            if !audit_entries(&audit_log).is_empty() {
+                break;
+            }
+            actix_rt::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let entries = audit_entries(&audit_log);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["action"], "update");
+        fs::remove_file(audit_log).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn unauthorized_changes_are_rejected() {
+        let mock = MockMeilisearch::start();
+        let (config, audit_log) = config("unauthorized");
+        let app = test_app!(mock, config);
+
+        for uri in [
+            format!("/admin/documents/{DOCUMENT_ID}"),
+            format!("/api/v1/admin/documents/{DOCUMENT_ID}"),
+        ] {
+            let requests = [
+                TestRequest::patch().uri(&uri).set_json(json!({ "title": "Outro" })),
+                authorized(TestRequest::patch().uri(&uri), "wrong").set_json(json!({ "title": "Outro" })),
+                TestRequest::delete().uri(&uri),
+                authorized(TestRequest::delete().uri(&uri), "wrong"),
+            ];
+            for request in requests {
+                let response = call_service(&app, request.to_request()).await;
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+                assert_eq!(response.headers().get(header::WWW_AUTHENTICATE).unwrap(), "Bearer");
+            }
+        }
+
+        // Nothing was changed
+        let response = call_service(
+            &app,
+            TestRequest::get()
+                .uri(&format!("/documents/{DOCUMENT_ID}/view"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(audit_entries(&audit_log).is_empty());
+    }
+}
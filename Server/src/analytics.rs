@@ -0,0 +1,475 @@
+use crate::admin::authenticate;
+use crate::config::Config;
+use crate::query::normalize;
+use crate::text::fold;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use chrono::{Days, NaiveDate, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool};
+use std::path::Path;
+use std::time::Duration;
+
+/// Name of the response header carrying the id of the search event, which the page sends back
+/// when a result is clicked.
+pub const EVENT_HEADER: &str = "X-Search-Event";
+
+/// Number of days covered by a report when no period is given.
+pub const DEFAULT_REPORT_DAYS: u64 = 30;
+
+/// Number of queries listed in each ranking of a report when no limit is given.
+const DEFAULT_REPORT_LIMIT: u32 = 20;
+
+/// Store of anonymized search events, kept in a local SQLite database. Events hold the folded query
+/// and what it returned, but nothing about who made it.
+pub struct Analytics {
+    pool: SqlitePool,
+}
+
+impl Analytics {
+    /// Opens the analytics database, creating it if needed. Returns `None` if it cannot be opened,
+    /// in which case searches are not recorded.
+    pub async fn open(path: &Path) -> Option<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+
+        let pool = match SqlitePool::connect_with(options).await {
+            Ok(pool) => pool,
+            Err(e) => {
+                error!("Could not open the analytics database {path:?}, searches will not be recorded: {e}");
+                return None;
+            },
+        };
+
+        Self::create(pool).await
+    }
+
+    /// Creates the analytics tables in a database if they do not exist yet. Returns `None` if they
+    /// cannot be created.
+    async fn create(pool: SqlitePool) -> Option<Self> {
+        let created = sqlx::query(
+            "CREATE TABLE IF NOT EXISTS search_events (
+                id INTEGER PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                query TEXT NOT NULL,
+                hits INTEGER NOT NULL,
+                filters TEXT NOT NULL,
+                collections TEXT NOT NULL,
+                latency_ms INTEGER NOT NULL,
+                clicked_document TEXT,
+                clicked_rank INTEGER
+            )",
+        )
+        .execute(&pool)
+        .await
+        .and(
+            sqlx::query("CREATE INDEX IF NOT EXISTS search_events_timestamp ON search_events (timestamp)")
+                .execute(&pool)
+                .await,
+        );
+        if let Err(e) = created {
+            error!("Could not create the analytics tables, searches will not be recorded: {e}");
+            return None;
+        }
+
+        Some(Self { pool })
+    }
+
+    /// Records a search and returns the id of its event. The query is normalized and cut to
+    /// `max_length` characters as it is for the search, then folded, so that the same search is
+    /// always counted under the same query. Failures are logged rather than returned, since
+    /// analytics must not get in the way of searches.
+    pub async fn record(
+        &self,
+        query: &str,
+        max_length: usize,
+        hits: usize,
+        filters: &[String],
+        collections: &[&str],
+        latency: Duration,
+    ) -> Option<i64> {
+        let result = sqlx::query(
+            "INSERT INTO search_events (timestamp, query, hits, filters, collections, latency_ms)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(Utc::now().timestamp())
+        .bind(fold(&normalize(query, max_length)))
+        .bind(i64::try_from(hits).unwrap_or(i64::MAX))
+        .bind(serde_json::to_string(filters).unwrap_or_default())
+        .bind(collections.join(","))
+        .bind(i64::try_from(latency.as_millis()).unwrap_or(i64::MAX))
+        .execute(&self.pool)
+        .await;
+
+        match result {
+            Ok(result) => Some(result.last_insert_rowid()),
+            Err(e) => {
+                error!("Could not record the search event: {e}");
+                None
+            },
+        }
+    }
+
+    /// Summarizes the searches made between two UTC days, both included: the number of searches,
+    /// the click-through rate, and the `limit` most frequent queries and queries without results.
+    /// Fails with a bad request error if the period ends on the last day chrono can represent.
+    pub async fn report(&self, from: NaiveDate, to: NaiveDate, limit: u32) -> Result<Report, Error> {
+        let start = from.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp();
+        let end = to
+            .checked_add_days(Days::new(1))
+            .ok_or_else(|| date_out_of_range(to))?
+            .and_hms_opt(0, 0, 0)
+            .unwrap_or_default()
+            .and_utc()
+            .timestamp();
+
+        let (searches, clicked_searches): (i64, i64) = sqlx::query_as(
+            "SELECT COUNT(*), COUNT(clicked_document) FROM search_events WHERE timestamp >= ? AND timestamp < ?",
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(database_error)?;
+
+        let ranking = |zero_results: bool| {
+            let pool = &self.pool;
+            async move {
+                let rows: Vec<(String, i64, f64, i64)> = sqlx::query_as(
+                    "SELECT query, COUNT(*) AS searches, AVG(hits), COUNT(clicked_document)
+                    FROM search_events
+                    WHERE timestamp >= ? AND timestamp < ? AND (? = 0 OR hits = 0)
+                    GROUP BY query
+                    ORDER BY searches DESC, query
+                    LIMIT ?",
+                )
+                .bind(start)
+                .bind(end)
+                .bind(zero_results)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+                .map_err(database_error)?;
+
+                Ok::<_, Error>(
+                    rows.into_iter()
+                        .map(|(query, searches, average_hits, clicks)| QueryCount {
+                            query,
+                            searches,
+                            average_hits,
+                            clicks,
+                        })
+                        .collect(),
+                )
+            }
+        };
+
+        let click_through_rate = if searches == 0 {
+            0.0
+        } else {
+            clicked_searches as f64 / searches as f64
+        };
+
+        Ok(Report {
+            from: from.to_string(),
+            to: to.to_string(),
+            searches,
+            clicked_searches,
+            click_through_rate,
+            top_queries: ranking(false).await?,
+            zero_result_queries: ranking(true).await?,
+        })
+    }
+}
+
+/// A result clicked on the search page.
+#[derive(Deserialize, Debug)]
+pub struct Click {
+    /// Id of the search event the result belongs to, from the `X-Search-Event` header.
+    event: i64,
+    /// Id of the document clicked.
+    document: String,
+    /// Position of the result in the list, starting at 1.
+    rank: u32,
+}
+
+/// Records the result clicked after a search. Only the first click of each search is kept, so the
+/// click-through rate counts searches that led somewhere rather than clicks.
+pub async fn click(click: web::Json<Click>, analytics: web::Data<Option<Analytics>>) -> HttpResponse {
+    if let Some(analytics) = analytics.as_ref() {
+        let result = sqlx::query(
+            "UPDATE search_events SET clicked_document = ?, clicked_rank = ?
+            WHERE id = ? AND clicked_document IS NULL",
+        )
+        .bind(&click.document)
+        .bind(click.rank)
+        .bind(click.event)
+        .execute(&analytics.pool)
+        .await;
+        if let Err(e) = result {
+            error!("Could not record the click on {}: {e}", click.document);
+        }
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+/// Query parameters of the analytics report.
+#[derive(Deserialize, Debug)]
+pub struct ReportQuery {
+    /// First day of the period, as YYYY-MM-DD. Defaults to 30 days before `to`.
+    from: Option<String>,
+    /// Last day of the period, as YYYY-MM-DD. Defaults to today.
+    to: Option<String>,
+    /// Number of queries in each ranking.
+    limit: Option<u32>,
+}
+
+/// A query in one of the rankings of the report.
+#[derive(Serialize)]
+pub struct QueryCount {
+    pub query: String,
+    pub searches: i64,
+    pub average_hits: f64,
+    pub clicks: i64,
+}
+
+/// Analytics over a period, in UTC days.
+#[derive(Serialize)]
+pub struct Report {
+    pub from: String,
+    pub to: String,
+    pub searches: i64,
+    pub clicked_searches: i64,
+    /// Share of the searches followed by a click on a result.
+    pub click_through_rate: f64,
+    pub top_queries: Vec<QueryCount>,
+    /// Queries that returned nothing, the candidates for new synonyms.
+    pub zero_result_queries: Vec<QueryCount>,
+}
+
+/// Parses a date parameter of the report.
+fn parse_date(value: &str) -> Result<NaiveDate, Error> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| actix_web::error::ErrorBadRequest(format!("Invalid date: {value}, expected YYYY-MM-DD")))
+}
+
+/// Returns the error given when a period cannot be extended past a day chrono can represent.
+fn date_out_of_range(day: NaiveDate) -> Error {
+    actix_web::error::ErrorBadRequest(format!("Date out of range: {day}"))
+}
+
+/// Logs a database error and turns it into an internal server error for the client.
+fn database_error(e: sqlx::Error) -> Error {
+    error!("Analytics Error: {e:?}");
+    actix_web::error::ErrorInternalServerError("Analytics query failed")
+}
+
+/// Reports, for the admins, the most frequent queries, those that returned no results and the
+/// click-through rate over a period.
+pub async fn report(
+    req: HttpRequest,
+    params: web::Query<ReportQuery>,
+    config: web::Data<Config>,
+    analytics: web::Data<Option<Analytics>>,
+) -> Result<HttpResponse, Error> {
+    authenticate(&req, &config)?;
+    let Some(analytics) = analytics.as_ref() else {
+        return Err(actix_web::error::ErrorServiceUnavailable("Analytics are not available"));
+    };
+
+    let to = params
+        .to
+        .as_deref()
+        .map_or_else(|| Ok(Utc::now().date_naive()), parse_date)?;
+    let from = match params.from.as_deref() {
+        Some(from) => parse_date(from)?,
+        None => to
+            .checked_sub_days(Days::new(DEFAULT_REPORT_DAYS))
+            .ok_or_else(|| date_out_of_range(to))?,
+    };
+    let report = analytics
+        .report(from, to, params.limit.unwrap_or(DEFAULT_REPORT_LIMIT))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use std::collections::HashMap;
+
+    /// Opens analytics over an in-memory database. The pool keeps a single connection for good, as
+    /// each connection to `:memory:` opens a database of its own.
+    async fn in_memory() -> Analytics {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        Analytics::create(pool).await.unwrap()
+    }
+
+    /// Returns the query and the click of every event recorded, in order.
+    async fn events(analytics: &Analytics) -> Vec<(String, Option<String>, Option<i64>)> {
+        sqlx::query_as("SELECT query, clicked_document, clicked_rank FROM search_events ORDER BY id")
+            .fetch_all(&analytics.pool)
+            .await
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn record_keeps_the_normalized_query() {
+        let analytics = in_memory().await;
+
+        let first = analytics
+            .record(
+                "  Licitação\t\u{7}Pública ",
+                200,
+                3,
+                &[],
+                &["entries"],
+                Duration::from_millis(12),
+            )
+            .await
+            .unwrap();
+        let second = analytics
+            .record(&"a".repeat(300), 200, 0, &[], &["entries"], Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(second > first);
+
+        let events = events(&analytics).await;
+        assert_eq!(events[0].0, "licitacao publica");
+        assert_eq!(events[1].0, "a".repeat(200));
+    }
+
+    #[actix_rt::test]
+    async fn only_the_first_click_of_a_search_is_kept() {
+        let analytics = web::Data::new(Some(in_memory().await));
+        let recorded = analytics.as_ref().as_ref().unwrap();
+        let event = recorded
+            .record("edital", 200, 2, &[], &["entries"], Duration::ZERO)
+            .await
+            .unwrap();
+
+        for (document, rank) in [("first", 1), ("second", 2)] {
+            let response = click(
+                web::Json(Click {
+                    event,
+                    document: document.to_string(),
+                    rank,
+                }),
+                analytics.clone(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+
+        assert_eq!(
+            events(recorded).await,
+            [("edital".to_string(), Some("first".to_string()), Some(1))]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn report_counts_the_searches_of_the_period() {
+        let analytics = in_memory().await;
+        for (query, hits) in [("edital", 3), ("edital", 5), ("vestibular", 0)] {
+            analytics
+                .record(query, 200, hits, &[], &["entries"], Duration::ZERO)
+                .await
+                .unwrap();
+        }
+        // A search made before the period
+        sqlx::query("UPDATE search_events SET timestamp = 0 WHERE query = 'vestibular'")
+            .execute(&analytics.pool)
+            .await
+            .unwrap();
+        analytics
+            .record("vestibular", 200, 0, &[], &["entries"], Duration::ZERO)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE search_events SET clicked_document = 'doc' WHERE id = 1")
+            .execute(&analytics.pool)
+            .await
+            .unwrap();
+
+        let today = Utc::now().date_naive();
+        let report = analytics.report(today, today, 10).await.unwrap();
+        assert_eq!(report.searches, 3);
+        assert_eq!(report.clicked_searches, 1);
+        assert!((report.click_through_rate - 1.0 / 3.0).abs() < 1e-9);
+        let top: Vec<_> = report
+            .top_queries
+            .iter()
+            .map(|query| (query.query.as_str(), query.searches, query.average_hits, query.clicks))
+            .collect();
+        assert_eq!(top, [("edital", 2, 4.0, 1), ("vestibular", 1, 0.0, 0)]);
+        let zero: Vec<_> = report
+            .zero_result_queries
+            .iter()
+            .map(|query| query.query.as_str())
+            .collect();
+        assert_eq!(zero, ["vestibular"]);
+
+        let limited = analytics.report(today, today, 1).await.unwrap();
+        assert_eq!(limited.top_queries.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn report_rejects_periods_past_the_representable_days() {
+        let analytics = in_memory().await;
+        let error = analytics
+            .report(NaiveDate::MIN, NaiveDate::MAX, 10)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), format!("Date out of range: {}", NaiveDate::MAX));
+
+        let config = web::Data::new(Config {
+            admin: crate::config::Admin {
+                tokens: HashMap::from([("secret".to_string(), "admin".to_string())]),
+                ..Default::default()
+            },
+            ..Config::default()
+        });
+        let analytics = web::Data::new(Some(analytics));
+        let report = |token: &str, from: Option<&str>, to: Option<&str>| {
+            let req = TestRequest::get()
+                .insert_header((actix_web::http::header::AUTHORIZATION, format!("Bearer {token}")))
+                .to_http_request();
+            let params = ReportQuery {
+                from: from.map(str::to_string),
+                to: to.map(str::to_string),
+                limit: None,
+            };
+            report(req, web::Query(params), config.clone(), analytics.clone())
+        };
+        let message = |result: Result<HttpResponse, Error>| result.err().map(|e| e.to_string());
+
+        assert_eq!(message(report("secret", None, None).await), None);
+        assert_eq!(
+            message(report("wrong", None, None).await).as_deref(),
+            Some("Invalid admin token")
+        );
+        assert_eq!(
+            message(report("secret", Some("2024-13-01"), None).await).as_deref(),
+            Some("Invalid date: 2024-13-01, expected YYYY-MM-DD")
+        );
+        // The default period would start before the first representable day
+        let first_day = NaiveDate::MIN.checked_add_days(Days::new(1)).unwrap().to_string();
+        assert_eq!(
+            message(report("secret", None, Some(&first_day)).await),
+            Some(format!("Date out of range: {first_day}"))
+        );
+    }
+}
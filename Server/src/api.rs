@@ -0,0 +1,242 @@
+//! The JSON API, served under `/api/v1/`. Responses are built from the schema types of [`v1`]
+//! rather than serialized straight from [`PDFdoc`], so that changes to the index or to the search
+//! code cannot change what clients receive. A breaking change gets a new version module and prefix,
+//! and `v1` is left as it is.
+
+use crate::{PDFdoc, admin, analytics, documents, export, i18n, live, rate_limit, references, search, stats, tokens};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{Error, web};
+use std::future::Future;
+
+/// Prefix of the current version of the API.
+pub const V1_PREFIX: &str = "/api/v1";
+
+/// Value of the Deprecation header of the routes kept from before the versioned API (RFC 9745):
+/// the day the API moved under [`V1_PREFIX`], as a Unix timestamp.
+const DEPRECATION: &str = "@1792281600";
+
+/// Registers the routes of the first version of the API, relative to [`V1_PREFIX`].
+pub fn configure_v1(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/search").route(web::get().to(search)))
+        .service(web::resource("/search/live").route(web::get().to(live::live_search)))
+        .service(web::resource("/export").route(web::get().to(export::export)))
+        .service(web::resource("/documents/{id}/related").route(web::get().to(documents::related)))
+        .service(web::resource("/documents/{id}/matches").route(web::get().to(documents::matches)))
+        .service(web::resource("/documents/{id}/references").route(web::get().to(references::references)))
+        .service(web::resource("/stats").route(web::get().to(stats::stats)))
+        .service(web::resource("/tokens").route(web::post().to(tokens::issue)))
+        .service(web::resource("/analytics/click").route(web::post().to(analytics::click)))
+        .service(web::resource("/admin/analytics").route(web::get().to(analytics::report)))
+        .service(web::resource("/admin/reindex").route(web::post().to(admin::reindex_documents)))
+        .service(
+            web::resource("/admin/documents/{id}")
+                .route(web::patch().to(admin::update_document))
+                .route(web::delete().to(admin::delete_document)),
+        );
+}
+
+/// Registers the routes deployed before the versioned API, as deprecated aliases of the same
+/// routes under [`V1_PREFIX`].
+pub fn configure_legacy(cfg: &mut web::ServiceConfig) {
+    let alias = |path| {
+        web::resource(path)
+            .wrap_fn(deprecated)
+            .wrap_fn(rate_limit::limit)
+            .wrap_fn(i18n::localize)
+    };
+    cfg.service(alias("/search").to(search))
+        .service(alias("/search/live").route(web::get().to(live::live_search)))
+        .service(alias("/export").route(web::get().to(export::export)))
+        .service(alias("/documents/{id}/related").route(web::get().to(documents::related)))
+        .service(alias("/analytics/click").route(web::post().to(analytics::click)))
+        .service(alias("/admin/analytics").route(web::get().to(analytics::report)));
+}
+
+/// Marks the responses of a route kept from before the versioned API as deprecated (RFC 9745),
+/// pointing clients to the same path under [`V1_PREFIX`].
+pub fn deprecated<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>> + 'static
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let successor = HeaderValue::from_str(&format!("<{V1_PREFIX}{}>; rel=\"successor-version\"", req.path()));
+    let response = srv.call(req);
+
+    async move {
+        let mut response = response.await?;
+        let headers = response.headers_mut();
+        headers.insert(
+            HeaderName::from_static("deprecation"),
+            HeaderValue::from_static(DEPRECATION),
+        );
+        if let Ok(successor) = successor {
+            headers.insert(header::LINK, successor);
+        }
+        Ok(response)
+    }
+}
+
+/// Schema of the responses of the first version of the API.
+pub mod v1 {
+    use super::PDFdoc;
+    use super::i18n::Locale;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    /// A document, as returned in search results and related documents.
+    #[derive(Serialize)]
+    pub struct Document<'a> {
+        pub id: &'a str,
+        pub title: &'a str,
+        /// Publication date, as a Unix timestamp.
+        pub date: i64,
+        pub content: &'a str,
+        /// Link to the original PDF on SIGRH.
+        pub link: &'a str,
+        /// Category of the document: 1 for normative, 2 for deliberative, 3 for unspecified.
+        pub is_normative: i32,
+        /// Name of the category in the language the client asked for, only set if it asked for one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub category: Option<&'static str>,
+        /// Name of the collection the document was found in, only set on search results.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub collection: Option<&'a str>,
+    }
+
+    impl<'a> From<&'a PDFdoc> for Document<'a> {
+        fn from(document: &'a PDFdoc) -> Self {
+            Self {
+                id: &document.id,
+                title: &document.title,
+                date: document.date,
+                content: &document.content,
+                link: &document.link,
+                is_normative: document.is_normative,
+                category: None,
+                collection: document.collection.as_deref(),
+            }
+        }
+    }
+
+    impl Document<'_> {
+        /// Adds the name of the category in the given locale, if any.
+        #[must_use]
+        pub fn localized(mut self, locale: Option<Locale>) -> Self {
+            self.category = locale.map(|locale| locale.category_label(self.is_normative));
+            self
+        }
+    }
+
+    /// Response of the search and related documents endpoints.
+    #[derive(Serialize)]
+    pub struct SearchResponse<'a> {
+        pub results: Vec<Document<'a>>,
+    }
+
+    /// A document linked from another one.
+    #[derive(Serialize)]
+    pub struct DocumentLink<'a> {
+        pub id: &'a str,
+        pub title: &'a str,
+        /// Name of the collection holding the document.
+        pub collection: &'a str,
+    }
+
+    /// A resolution cited by a document, with the indexed documents publishing it. Resolutions
+    /// that are not indexed have no documents.
+    #[derive(Serialize)]
+    pub struct Citation<'a> {
+        /// The resolution, as usually written, e.g. “Resolução CAD nº 45/2020”.
+        pub citation: String,
+        pub documents: Vec<DocumentLink<'a>>,
+    }
+
+    /// Response of the references endpoint.
+    #[derive(Serialize)]
+    pub struct References<'a> {
+        /// Resolutions the document cites.
+        pub cites: Vec<Citation<'a>>,
+        /// Documents citing the resolution the document publishes.
+        pub cited_by: Vec<DocumentLink<'a>>,
+    }
+
+    /// Results of a query, pushed on the live search connection.
+    #[derive(Serialize)]
+    pub struct LiveResults<'a> {
+        /// Query the results are for, so that the page can drop results of queries it no longer
+        /// shows.
+        pub q: &'a str,
+        pub results: Vec<Document<'a>>,
+        /// Id of the analytics event of the search, to be sent back when a result is clicked.
+        pub event: Option<i64>,
+    }
+
+    /// An occurrence of the search terms in a document.
+    #[derive(Serialize)]
+    pub struct Match<'a> {
+        /// The matched text, as written in the document.
+        pub text: &'a str,
+        /// Offset of the first character of the match in the content of the document, counted in
+        /// Unicode characters.
+        pub start: usize,
+        /// Offset of the character following the match.
+        pub end: usize,
+        /// Page of the PDF the match starts on, counting from 1.
+        pub page: usize,
+        /// Text preceding the match, with whitespace collapsed.
+        pub before: String,
+        /// Text following the match, with whitespace collapsed.
+        pub after: String,
+    }
+
+    /// Response of the in-document search endpoint.
+    #[derive(Serialize)]
+    pub struct Matches<'a> {
+        pub q: &'a str,
+        /// Every match, in order of appearance.
+        pub matches: Vec<Match<'a>>,
+    }
+
+    /// Response of the statistics endpoint.
+    #[derive(Serialize)]
+    pub struct Statistics<'a> {
+        /// Number of indexed documents.
+        pub documents: usize,
+        /// Number of documents by category: normative, deliberative or unspecified.
+        pub by_category: BTreeMap<&'a str, usize>,
+        /// Number of documents by year of publication, leaving out those without a known date.
+        pub by_year: BTreeMap<i32, usize>,
+        /// Total size of the text of the documents, in bytes.
+        pub content_size: usize,
+        /// Publication date of the oldest document, as YYYY-MM-DD.
+        pub oldest_date: Option<String>,
+        /// Publication date of the newest document, as YYYY-MM-DD.
+        pub newest_date: Option<String>,
+        /// Last time the index was updated, in RFC 3339 format.
+        pub last_update: Option<String>,
+    }
+
+    /// A tenant token, to search an index of Meilisearch directly.
+    #[derive(Serialize)]
+    pub struct TenantToken<'a> {
+        pub token: String,
+        /// Index the token allows searching.
+        pub index: &'a str,
+        /// Address of Meilisearch to send the searches to.
+        pub meilisearch_url: &'a str,
+        /// Expiration of the token, in RFC 3339 format.
+        pub expires_at: String,
+    }
+
+    /// A query of the live search connection that could not be searched.
+    #[derive(Serialize)]
+    pub struct LiveError<'a> {
+        pub q: &'a str,
+        pub error: String,
+    }
+}
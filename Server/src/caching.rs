@@ -0,0 +1,50 @@
+use actix_web::http::header::{
+    CACHE_CONTROL, CacheControl, CacheDirective, ETAG, ETag, EntityTag, Header, IfNoneMatch, TryIntoHeaderValue,
+};
+use actix_web::{HttpRequest, HttpResponse};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Returns a strong ETag identifying the given value, for responses built from it.
+pub fn etag(value: &impl Hash) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    EntityTag::new_strong(format!("{:016x}", hasher.finish()))
+}
+
+/// Tells whether the client already has the version of the response identified by the ETag.
+fn not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+/// Returns a `Cache-Control` header letting browsers and proxies reuse a response for `max_age`
+/// seconds.
+pub fn cache_control(max_age: u32) -> CacheControl {
+    CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(max_age)])
+}
+
+/// Answers a conditional request. If the client already has the version identified by the ETag,
+/// a 304 response is returned without calling `build`. Otherwise the response built is returned
+/// with the ETag and caching headers.
+pub fn respond(req: &HttpRequest, etag: EntityTag, max_age: u32, build: impl FnOnce() -> HttpResponse) -> HttpResponse {
+    let mut response = if not_modified(req, &etag) {
+        HttpResponse::NotModified().finish()
+    } else {
+        build()
+    };
+
+    if response.status().is_success() || response.status().is_redirection() {
+        if let Ok(value) = ETag(etag).try_into_value() {
+            response.headers_mut().insert(ETAG, value);
+        }
+        if let Ok(value) = cache_control(max_age).try_into_value() {
+            response.headers_mut().insert(CACHE_CONTROL, value);
+        }
+    }
+
+    response
+}
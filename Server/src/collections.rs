@@ -0,0 +1,235 @@
+use crate::config::Config;
+use crate::query::{ParsedQuery, SortOrder};
+use crate::resilience::Guard;
+use crate::{PDFdoc, query_meilisearch};
+use actix_web::Error;
+use futures_util::future::try_join_all;
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::search::SearchResult;
+use std::cmp::Ordering;
+
+/// A collection of documents picked by a request, and the Meilisearch index holding it.
+pub struct Collection<'a> {
+    pub name: &'a str,
+    pub index: &'a str,
+}
+
+/// Returns the collections named by the `collection` parameter of a request: a single name, a
+/// comma-separated list of names, or `*` for every collection. Without the parameter, the default
+/// collection is used. Fails with a bad request error if a name is not a configured collection.
+pub fn resolve<'a>(config: &'a Config, parameter: Option<&str>) -> Result<Vec<Collection<'a>>, Error> {
+    let collection = |(name, index): (&'a String, &'a String)| Collection { name, index };
+
+    match parameter.map(str::trim) {
+        None | Some("") => Ok(config
+            .collections
+            .get_key_value(&config.default_collection)
+            .map(collection)
+            .into_iter()
+            .collect()),
+        Some("*") => Ok(config.collections.iter().map(collection).collect()),
+        Some(names) => {
+            let mut collections: Vec<Collection> = Vec::new();
+            for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                let Some(found) = config.collections.get_key_value(name) else {
+                    return Err(actix_web::error::ErrorBadRequest(format!("Unknown collection: {name}")));
+                };
+                if collections.iter().all(|collection| collection.name != name) {
+                    collections.push(collection(found));
+                }
+            }
+            Ok(collections)
+        },
+    }
+}
+
+/// Returns the collection named by the `collection` parameter of a request on a single document,
+/// the default collection without it. Fails with a bad request error if the parameter does not
+/// name exactly one configured collection.
+pub fn resolve_one<'a>(config: &'a Config, parameter: Option<&str>) -> Result<Collection<'a>, Error> {
+    let mut collections = resolve(config, parameter)?;
+    match (collections.pop(), collections.is_empty()) {
+        (Some(collection), true) => Ok(collection),
+        _ => Err(actix_web::error::ErrorBadRequest(
+            "A document belongs to a single collection",
+        )),
+    }
+}
+
+/// Returns a collection for every index holding documents, the default collection first. Indexes
+/// shared by several collections are only listed once, under the first of their names, so that
+/// their documents are not counted or listed twice.
+pub fn by_index(config: &Config) -> Vec<Collection<'_>> {
+    let mut collections: Vec<Collection> = Vec::new();
+    let default = config.collections.get_key_value(&config.default_collection);
+    for (name, index) in default.into_iter().chain(&config.collections) {
+        if collections.iter().all(|collection| collection.index != index) {
+            collections.push(Collection { name, index });
+        }
+    }
+    collections
+}
+
+/// Searches several collections at once and merges their hits into a single list of at most
+/// `limit` results, starting at `offset`. Each collection is searched separately, so the hits are
+/// re-ranked by the ranking score Meilisearch gives them, which is comparable across indexes, or
+/// by date if the query asks for it. Every result is tagged with the name of the collection it
+/// came from.
+pub async fn federated_search(
+    query: &ParsedQuery,
+    client: &Client,
+    guard: &Guard,
+    collections: &[Collection<'_>],
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<SearchResult<PDFdoc>>, Error> {
+    // A single collection is paged by Meilisearch, otherwise the pages can only be cut once the
+    // hits of every collection are merged
+    let (search_offset, search_limit, skipped) = match collections {
+        [_] => (offset, limit, 0),
+        _ => (0, offset.saturating_add(limit), offset),
+    };
+
+    let searches = collections.iter().map(|collection| async move {
+        let search_results =
+            query_meilisearch(query, client, guard, collection.index, search_offset, search_limit).await?;
+        Ok::<_, Error>(search_results.hits.into_iter().map(|mut hit| {
+            hit.result.collection = Some(collection.name.to_string());
+            hit
+        }))
+    });
+
+    let mut hits: Vec<SearchResult<PDFdoc>> = try_join_all(searches).await?.into_iter().flatten().collect();
+    hits.sort_by(|a, b| match query.sort {
+        SortOrder::Relevance => b.ranking_score.partial_cmp(&a.ranking_score).unwrap_or(Ordering::Equal),
+        SortOrder::Newest => b.result.date.cmp(&a.result.date),
+        SortOrder::Oldest => a.result.date.cmp(&b.result.date),
+    });
+
+    Ok(hits.into_iter().skip(skipped).take(limit).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Resilience;
+    use crate::mock_meilisearch::{self, MockMeilisearch};
+    use std::collections::BTreeMap;
+
+    /// Returns a configuration with the collections `atas` and `resolucoes`, both held by the
+    /// fixture index.
+    fn config() -> Config {
+        Config {
+            collections: BTreeMap::from([
+                ("atas".to_string(), "entries".to_string()),
+                ("resolucoes".to_string(), "entries".to_string()),
+            ]),
+            default_collection: "resolucoes".to_string(),
+            ..Config::default()
+        }
+    }
+
+    fn names(collections: &[Collection]) -> Vec<String> {
+        collections
+            .iter()
+            .map(|collection| collection.name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let config = config();
+        let resolved = |parameter| resolve(&config, parameter).map(|collections| names(&collections));
+
+        assert_eq!(resolved(None).unwrap(), ["resolucoes"]);
+        assert_eq!(resolved(Some(" ")).unwrap(), ["resolucoes"]);
+        assert_eq!(resolved(Some("*")).unwrap(), ["atas", "resolucoes"]);
+        assert_eq!(
+            resolved(Some("resolucoes, atas,,resolucoes")).unwrap(),
+            ["resolucoes", "atas"]
+        );
+        assert_eq!(
+            resolved(Some("atas,editais")).unwrap_err().to_string(),
+            "Unknown collection: editais"
+        );
+        assert!(resolved(Some("Atas")).is_err());
+
+        let resolved_one = |parameter| resolve_one(&config, parameter).map(|collection| collection.name);
+        assert_eq!(resolved_one(None).unwrap(), "resolucoes");
+        assert_eq!(resolved_one(Some("atas")).unwrap(), "atas");
+        assert!(resolved_one(Some("atas,resolucoes")).is_err());
+        assert!(resolved_one(Some("*")).is_err());
+    }
+
+    #[test]
+    fn shared_indexes_are_listed_once() {
+        let mut config = config();
+        config.collections.insert("editais".to_string(), "editais".to_string());
+
+        let collections = by_index(&config);
+        assert_eq!(names(&collections), ["resolucoes", "editais"]);
+        assert_eq!(collections[0].index, "entries");
+    }
+
+    #[actix_rt::test]
+    async fn federated_hits_are_merged_and_paged() {
+        let mock = MockMeilisearch::start();
+        let client = Client::new(&mock.url, Some(mock_meilisearch::API_KEY));
+        let guard = Guard::new(Resilience::default());
+        let config = config();
+        let collections = resolve(&config, Some("*")).unwrap();
+        let search = |query: ParsedQuery, offset, limit| {
+            let (client, guard, collections) = (&client, &guard, &collections);
+            async move {
+                federated_search(&query, client, guard, collections, offset, limit)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|hit| {
+                        (
+                            hit.result.collection.unwrap(),
+                            hit.result.date,
+                            hit.ranking_score.unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // Each collection holds the same four matches, so they come in pairs of equal score
+        let hits = search(ParsedQuery::parse("cao"), 0, 20).await;
+        assert_eq!(hits.len(), 8);
+        for (rank, pair) in hits.chunks(2).enumerate() {
+            assert_eq!(pair[0].0, "atas");
+            assert_eq!(pair[1].0, "resolucoes");
+            assert_eq!(pair[0].2, 1.0 / (rank as f64 + 1.0));
+            assert_eq!(pair[1].2, pair[0].2);
+        }
+
+        // Pages are cut from the merged hits, each collection being asked for every hit up to the
+        // end of the page
+        mock.clear_searches();
+        assert_eq!(search(ParsedQuery::parse("cao"), 3, 3).await, hits[3..6]);
+        let searches = mock.searches();
+        assert_eq!(searches.len(), 2);
+        for search in searches {
+            assert_eq!(search["offset"], 0);
+            assert_eq!(search["limit"], 6);
+        }
+        assert!(search(ParsedQuery::parse("cao"), 8, 3).await.is_empty());
+
+        let oldest = ParsedQuery {
+            sort: SortOrder::Oldest,
+            ..ParsedQuery::parse("cao")
+        };
+        let dates: Vec<i64> = search(oldest, 0, 20).await.into_iter().map(|hit| hit.1).collect();
+        assert_eq!(dates.len(), 8);
+        assert!(dates.is_sorted());
+        let newest = ParsedQuery {
+            sort: SortOrder::Newest,
+            ..ParsedQuery::parse("cao")
+        };
+        let dates: Vec<i64> = search(newest, 0, 20).await.into_iter().map(|hit| hit.1).collect();
+        assert!(dates.is_sorted_by(|a, b| a >= b));
+    }
+}
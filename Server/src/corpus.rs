@@ -0,0 +1,259 @@
+use crate::collections;
+use crate::config::Config;
+use crate::references::ReferenceGraph;
+use crate::resilience::Guard;
+use crate::stats::Statistics;
+use crate::text::fold;
+use actix_web::{Error, web};
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::documents::DocumentsQuery;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// How long a corpus snapshot is reused before being fetched again from Meilisearch.
+const CORPUS_TTL: Duration = Duration::from_secs(600);
+
+/// Number of documents requested per page when building a snapshot.
+const PAGE_SIZE: usize = 500;
+
+/// Words that carry no meaning on their own and should never be picked as distinctive terms.
+const STOP_WORDS: &[&str] = &[
+    "aos", "com", "como", "das", "dos", "ela", "ele", "essa", "esse", "esta", "este", "isso", "mais", "mas", "nao",
+    "nas", "nem", "nos", "num", "numa", "para", "pela", "pelas", "pelo", "pelos", "por", "que", "quando", "sem", "seu",
+    "seus", "sob", "sobre", "sua", "suas", "tem", "uma", "umas", "uns",
+];
+
+/// The fields of each indexed document needed to compute the corpus statistics and the references
+/// between documents.
+#[derive(Deserialize)]
+struct CorpusEntry {
+    id: String,
+    title: String,
+    date: i64,
+    content: String,
+    is_normative: i32,
+}
+
+/// Statistics over every indexed document of every collection, used to weigh terms by how
+/// distinctive they are.
+pub struct Corpus {
+    /// Number of indexed documents.
+    size: usize,
+    /// Number of documents each term appears in.
+    document_frequency: HashMap<String, usize>,
+    /// Citations between the indexed documents.
+    pub references: ReferenceGraph,
+    /// Counts of the indexed documents.
+    pub statistics: Statistics,
+}
+
+impl Corpus {
+    /// Returns an empty corpus.
+    fn new() -> Self {
+        Self {
+            size: 0,
+            document_frequency: HashMap::new(),
+            references: ReferenceGraph::default(),
+            statistics: Statistics::default(),
+        }
+    }
+
+    /// Fetches every document of each index, see [`collections::by_index`], page by page, counts
+    /// in how many of them each term appears, collects the resolutions they cite and counts them
+    /// by category and year. The pages are processed on the blocking thread pool, so that the
+    /// workers keep serving requests.
+    async fn fetch(client: &Client, guard: &Guard, config: &Config) -> Result<Self, Error> {
+        let mut corpus = Self::new();
+
+        for collection in collections::by_index(config) {
+            let index = guard.call(|| client.get_index(collection.index)).await?;
+            corpus.statistics.updated(index.updated_at);
+
+            let mut offset = 0;
+            loop {
+                let mut query = DocumentsQuery::new(&index);
+                query.with_offset(offset).with_limit(PAGE_SIZE).with_fields([
+                    "id",
+                    "title",
+                    "date",
+                    "content",
+                    "is_normative",
+                ]);
+                let page = guard.call(|| query.execute::<CorpusEntry>()).await?;
+
+                let fetched = page.results.len();
+                let total = page.total as usize;
+                let name = collection.name.to_string();
+                corpus = web::block(move || {
+                    for entry in page.results {
+                        corpus.add(entry, &name);
+                    }
+                    corpus
+                })
+                .await?;
+
+                offset += fetched;
+                if fetched == 0 || offset >= total {
+                    break;
+                }
+            }
+        }
+
+        Ok(corpus)
+    }
+
+    /// Adds a document of a collection to the corpus.
+    fn add(&mut self, entry: CorpusEntry, collection: &str) {
+        let unique_terms: HashSet<String> = terms(&entry.content).into_iter().collect();
+        for term in unique_terms {
+            *self.document_frequency.entry(term).or_default() += 1;
+        }
+        self.statistics.add(entry.date, entry.is_normative, &entry.content);
+        self.references.add(entry.id, entry.title, collection, &entry.content);
+        self.size += 1;
+    }
+
+    /// Returns up to `count` terms of the given text with the highest TF-IDF weight, the most
+    /// distinctive first. Terms found in every document of the corpus are never returned.
+    pub fn distinctive_terms(&self, text: &str, count: usize) -> Vec<String> {
+        let mut term_frequency: HashMap<String, usize> = HashMap::new();
+        for term in terms(text) {
+            *term_frequency.entry(term).or_default() += 1;
+        }
+
+        let mut weighted: Vec<(f64, String)> = term_frequency
+            .into_iter()
+            .map(|(term, frequency)| {
+                let document_frequency = self.document_frequency.get(&term).copied().unwrap_or_default();
+                let idf = ((self.size + 1) as f64 / (document_frequency + 1) as f64).ln();
+                (frequency as f64 * idf, term)
+            })
+            .filter(|(weight, _)| *weight > 0.0)
+            .collect();
+
+        weighted.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        weighted.into_iter().take(count).map(|(_, term)| term).collect()
+    }
+}
+
+/// Splits a text into folded terms, skipping numbers, stop words and words shorter than three
+/// characters.
+fn terms(text: &str) -> Vec<String> {
+    fold(text)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() >= 3 && !term.chars().all(char::is_numeric))
+        .filter(|term| !STOP_WORDS.contains(term))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Keeps the latest corpus snapshot shared between the workers, rebuilding it once it is older
+/// than [`CORPUS_TTL`].
+#[derive(Default)]
+pub struct CorpusCache {
+    snapshot: RwLock<Option<(Instant, Arc<Corpus>)>>,
+    /// Held while a snapshot is being built, so that only one is built at a time.
+    rebuild: Mutex<()>,
+}
+
+impl CorpusCache {
+    /// Returns the current corpus snapshot, fetching a new one from Meilisearch if there is none or
+    /// if it has expired. While a new snapshot is being built, the expired one is returned to the
+    /// other requests, and those arriving before the first one is built wait for it.
+    pub async fn get(&self, client: &Client, guard: &Guard, config: &Config) -> Result<Arc<Corpus>, Error> {
+        let current = self.current();
+        if let Some(corpus) = fresh(&current) {
+            return Ok(corpus);
+        }
+
+        let _rebuild = match (self.rebuild.try_lock(), current) {
+            (Ok(rebuild), _) => rebuild,
+            (Err(_), Some((_, corpus))) => return Ok(corpus),
+            (Err(_), None) => self.rebuild.lock().await,
+        };
+        // The snapshot may have been rebuilt while waiting for the lock
+        if let Some(corpus) = fresh(&self.current()) {
+            return Ok(corpus);
+        }
+
+        let corpus = Arc::new(Corpus::fetch(client, guard, config).await?);
+        *self.snapshot.write().expect("Corpus cache lock poisoned.") = Some((Instant::now(), Arc::clone(&corpus)));
+
+        Ok(corpus)
+    }
+
+    /// Returns the current snapshot and when it was built, if there is one.
+    fn current(&self) -> Option<(Instant, Arc<Corpus>)> {
+        self.snapshot.read().expect("Corpus cache lock poisoned.").clone()
+    }
+}
+
+/// Returns the corpus of a snapshot if it has not expired.
+fn fresh(snapshot: &Option<(Instant, Arc<Corpus>)>) -> Option<Arc<Corpus>> {
+    snapshot
+        .as_ref()
+        .filter(|(built_at, _)| built_at.elapsed() < CORPUS_TTL)
+        .map(|(_, corpus)| Arc::clone(corpus))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a corpus from the contents of its documents.
+    fn corpus(contents: &[&str]) -> Corpus {
+        let mut corpus = Corpus::new();
+        for (number, content) in contents.iter().enumerate() {
+            corpus.add(
+                CorpusEntry {
+                    id: number.to_string(),
+                    title: format!("Documento {number}"),
+                    date: 0,
+                    content: content.to_string(),
+                    is_normative: 1,
+                },
+                "sigrh",
+            );
+        }
+        corpus
+    }
+
+    #[test]
+    fn test_terms_are_folded_and_filtered() {
+        assert_eq!(
+            terms("Art. 1º A PROGRESSÃO, de 2019, não é para os técnicos-administrativos"),
+            ["art", "progressao", "tecnicos", "administrativos"]
+        );
+        assert!(terms("de 12/2019 a o é 100").is_empty());
+        assert_eq!(terms("Ensino ensino"), ["ensino", "ensino"]);
+    }
+
+    #[test]
+    fn test_distinctive_terms_are_weighted_by_rarity() {
+        let corpus = corpus(&[
+            "Resolução sobre o trancamento de matrícula",
+            "Resolução sobre a progressão de carreira",
+            "Resolução sobre a progressão dos técnicos",
+            "Resolução sobre as férias",
+        ]);
+        assert_eq!(corpus.size, 4);
+
+        // "resolucao" is in every document, "progressao" in half of them
+        let text = "Resolução resolução: progressão, progressão e trancamento";
+        assert_eq!(corpus.distinctive_terms(text, 3), ["progressao", "trancamento"]);
+        assert_eq!(corpus.distinctive_terms(text, 1), ["progressao"]);
+        assert_eq!(
+            corpus.distinctive_terms("trancamento progressao", 0),
+            Vec::<String>::new()
+        );
+
+        // Terms that are not in the corpus are the most distinctive, ties sorted alphabetically
+        assert_eq!(
+            corpus.distinctive_terms("trancamento greve aposentadoria", 3),
+            ["aposentadoria", "greve", "trancamento"]
+        );
+    }
+}
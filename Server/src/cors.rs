@@ -0,0 +1,93 @@
+use crate::config::{Cors, LiveSettings};
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::{Error, HttpResponse, web};
+use futures_util::future::{self, Either};
+use std::future::Future;
+
+impl Cors {
+    /// Tells whether requests from the given origin are allowed.
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Tells whether cross-origin requests with the given method are allowed.
+    fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+
+    /// Adds the headers allowing the origin to read the response. The allowed origin is echoed
+    /// back rather than sent as `*`, so responses vary with the Origin header.
+    fn add_headers(headers: &mut HeaderMap, origin: HeaderValue) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+
+    /// Answers a preflight request, which browsers send before cross-origin requests that are not
+    /// simple GETs.
+    fn preflight(&self, origin: HeaderValue) -> HttpResponse {
+        let mut response = HttpResponse::NoContent()
+            .insert_header((header::ACCESS_CONTROL_ALLOW_METHODS, self.allowed_methods.join(", ")))
+            .insert_header((header::ACCESS_CONTROL_ALLOW_HEADERS, self.allowed_headers.join(", ")))
+            .insert_header((header::ACCESS_CONTROL_MAX_AGE, self.max_age))
+            .finish();
+        Self::add_headers(response.headers_mut(), origin);
+        response
+    }
+}
+
+/// Applies the CORS policy of the current settings. Preflight requests from allowed origins are
+/// answered directly and other requests from them get the headers letting the browser read the
+/// response. Requests from other origins are passed along untouched, so the browser blocks them,
+/// except preflights, which are refused.
+pub fn cors<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>> + 'static
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let policy = req
+        .app_data::<web::Data<LiveSettings>>()
+        .map(|settings| settings.get().cors.clone())
+        .unwrap_or_default();
+    let origin = req
+        .headers()
+        .get(header::ORIGIN)
+        .filter(|origin| origin.to_str().is_ok_and(|origin| policy.allows_origin(origin)))
+        .cloned();
+    let requested_method = req
+        .headers()
+        .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+        .map(|method| method.to_str().unwrap_or_default().to_string());
+
+    match (req.method() == Method::OPTIONS, requested_method, origin) {
+        (true, Some(method), origin) => {
+            let response = match origin {
+                Some(origin) if policy.allows_method(&method) => policy.preflight(origin),
+                _ => HttpResponse::Forbidden().finish(),
+            };
+            Either::Right(future::ok(req.into_response(response).map_into_right_body()))
+        },
+        (_, _, Some(origin)) if policy.allows_method(req.method().as_str()) => {
+            let response = srv.call(req);
+            Either::Left(Either::Left(async move {
+                let mut response = response.await?;
+                Cors::add_headers(response.headers_mut(), origin);
+                Ok(response.map_into_left_body())
+            }))
+        },
+        _ => {
+            let response = srv.call(req);
+            Either::Left(Either::Right(async move { Ok(response.await?.map_into_left_body()) }))
+        },
+    }
+}
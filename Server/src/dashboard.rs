@@ -0,0 +1,714 @@
+//! Web dashboard for the admins, served at `/admin`. It gathers on one page what operating the
+//! system otherwise takes curl, Meilisearch's own UI and the PopulateDB menu for: the health of
+//! Meilisearch and of the indexes, the latest tasks, the document counts and the search analytics.
+//! Admins can also correct the metadata of a document and start a re-indexing from it.
+//!
+//! Browsers cannot send bearer tokens on their own, so the dashboard also accepts HTTP Basic
+//! authentication, with any user name and an admin token as the password. Forms are only accepted
+//! when posted from the dashboard itself, as browsers would send the credentials along with a form
+//! posted from another site.
+//!
+//! The page of a document also answers the `PATCH` and `DELETE` requests of the admin API, so that
+//! corrections can be made at `/admin/documents/{id}` as well as under `/api/v1`.
+
+use crate::admin::{self, DocumentPatch, Settled};
+use crate::analytics::{Analytics, DEFAULT_REPORT_DAYS, QueryCount};
+use crate::config::Config;
+use crate::corpus::CorpusCache;
+use crate::documents::fetch_document;
+use crate::html::{escape, page};
+use crate::i18n::Locale;
+use crate::resilience::Guard;
+use crate::{PDFdoc, sitemap};
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{Days, NaiveDate, Utc};
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::tasks::{Task, TaskType, TasksSearchQuery};
+use serde::Deserialize;
+use sqlx::MySqlPool;
+use std::fmt::Write;
+use time::format_description::well_known::Rfc3339;
+
+/// Number of Meilisearch tasks listed on the dashboard.
+const RECENT_TASKS: u32 = 10;
+
+/// Number of queries in each ranking of the analytics shown on the dashboard.
+const REPORT_LIMIT: u32 = 10;
+
+/// Number of documents listed when looking for a document to edit.
+const DOCUMENT_RESULTS: usize = 20;
+
+/// Registers the routes of the dashboard, relative to `/admin`.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("").route(web::get().to(overview)))
+        .service(web::resource("/reindex").route(web::post().to(reindex)))
+        .service(
+            web::resource("/documents/{id}")
+                .route(web::get().to(edit))
+                .route(web::post().to(save))
+                .route(web::patch().to(admin::update_document))
+                .route(web::delete().to(admin::delete_document)),
+        );
+}
+
+/// Returns the name of the admin using the dashboard, identified by the password of the Basic
+/// credentials or by a bearer token. Fails with a challenge asking the browser for credentials.
+fn authenticate(req: &HttpRequest, config: &Config) -> Result<String, Error> {
+    let basic_admin = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|credentials| STANDARD.decode(credentials.trim()).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            let (_, token) = credentials.split_once(':')?;
+            config.admin.tokens.get(token).cloned()
+        });
+    if let Some(admin) = basic_admin {
+        return Ok(admin);
+    }
+
+    admin::authenticate(req, config).map_err(|_| {
+        actix_web::error::InternalError::from_response(
+            "Invalid admin credentials",
+            HttpResponse::Unauthorized()
+                .insert_header((
+                    header::WWW_AUTHENTICATE,
+                    r#"Basic realm="Admin dashboard", charset="UTF-8""#,
+                ))
+                .body("Invalid admin credentials"),
+        )
+        .into()
+    })
+}
+
+/// Rejects a form unless the `Origin` header, or the `Referer` header for browsers that leave it
+/// out, shows it was posted from a page of the server.
+fn check_origin(req: &HttpRequest, config: &Config) -> Result<(), Error> {
+    let base_url = sitemap::base_url(req, config);
+    let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let same_origin = match (header(header::ORIGIN), header(header::REFERER)) {
+        (Some(origin), _) => origin == base_url,
+        (None, Some(referer)) => referer
+            .strip_prefix(&base_url)
+            .is_some_and(|path| path.starts_with('/')),
+        (None, None) => false,
+    };
+
+    if same_origin {
+        Ok(())
+    } else {
+        Err(actix_web::error::ErrorForbidden(
+            "Forms must be sent from the dashboard",
+        ))
+    }
+}
+
+/// Returns a dashboard page, which browsers and proxies must not store. The dashboard is only
+/// written in English.
+fn html_response(title: &str, body: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(page(Locale::En, title, "", body))
+}
+
+/// Redirects the browser to another page of the dashboard after a form was handled.
+fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Query parameters of the overview page.
+#[derive(Deserialize, Debug)]
+pub struct OverviewQuery {
+    /// Search for documents to edit.
+    q: Option<String>,
+    /// Uid of the re-indexing task just started.
+    reindexed: Option<u32>,
+}
+
+/// Writes the state of Meilisearch, of the circuit breaker and of each index.
+async fn write_health(body: &mut String, client: &Client, guard: &Guard, config: &Config) {
+    let available = guard
+        .call(|| client.health())
+        .await
+        .is_ok_and(|health| health.status == "available");
+    let _ = write!(
+        body,
+        r#"<section>
+<h2>Health</h2>
+<p>Meilisearch is <strong>{}</strong>, the circuit breaker is <strong>{}</strong>.</p>
+<table>
+<thead><tr><th>Collection</th><th>Index</th><th>Documents</th><th>Indexing</th></tr></thead>
+<tbody>
+"#,
+        if available { "available" } else { "unavailable" },
+        guard.circuit_state(),
+    );
+
+    for (name, index) in &config.collections {
+        let _ = write!(body, "<tr><td>{}</td><td>{}</td>", escape(name), escape(index));
+        let index = client.index(index);
+        match guard.call(|| index.get_stats()).await {
+            Ok(stats) => {
+                let _ = writeln!(
+                    body,
+                    "<td>{}</td><td>{}</td></tr>",
+                    stats.number_of_documents,
+                    if stats.is_indexing { "yes" } else { "no" }
+                );
+            },
+            Err(e) => {
+                let _ = writeln!(body, r#"<td colspan="2">{}</td></tr>"#, escape(&e.to_string()));
+            },
+        }
+    }
+    body.push_str("</tbody>\n</table>\n</section>\n");
+}
+
+/// Returns a readable name for the type of a Meilisearch task.
+const fn task_type_name(update_type: &TaskType) -> &'static str {
+    match update_type {
+        TaskType::DocumentAdditionOrUpdate { .. } => "Document addition or update",
+        TaskType::DocumentDeletion { .. } => "Document deletion",
+        TaskType::SettingsUpdate { .. } => "Settings update",
+        TaskType::IndexCreation { .. } => "Index creation",
+        TaskType::IndexUpdate { .. } => "Index update",
+        TaskType::IndexDeletion { .. } => "Index deletion",
+        TaskType::DumpCreation { .. } => "Dump creation",
+        _ => "Other",
+    }
+}
+
+/// Writes the latest Meilisearch tasks, with the error of those that failed.
+async fn write_tasks(body: &mut String, client: &Client, guard: &Guard) {
+    body.push_str("<section>\n<h2>Recent tasks</h2>\n");
+    let mut query = TasksSearchQuery::new(client);
+    query.with_limit(RECENT_TASKS);
+    let tasks = match guard.call(|| client.get_tasks_with(&query)).await {
+        Ok(tasks) => tasks.results,
+        Err(e) => {
+            let _ = writeln!(
+                body,
+                "<p>Could not list the tasks: {}</p>\n</section>",
+                escape(&e.to_string())
+            );
+            return;
+        },
+    };
+
+    body.push_str(concat!(
+        "<table>\n<thead><tr><th>Task</th><th>Index</th><th>Type</th><th>Status</th><th>Enqueued at</th>",
+        "<th>Error</th></tr></thead>\n<tbody>\n",
+    ));
+    for task in &tasks {
+        let (status, index, update_type, enqueued_at, error) = match task {
+            Task::Enqueued { content } => (
+                "enqueued",
+                &content.index_uid,
+                &content.update_type,
+                content.enqueued_at,
+                None,
+            ),
+            Task::Processing { content } => (
+                "processing",
+                &content.index_uid,
+                &content.update_type,
+                content.enqueued_at,
+                None,
+            ),
+            Task::Failed { content } => (
+                "failed",
+                &content.task.index_uid,
+                &content.task.update_type,
+                content.task.enqueued_at,
+                Some(content.error.error_message.as_str()),
+            ),
+            Task::Succeeded { content } => (
+                "succeeded",
+                &content.index_uid,
+                &content.update_type,
+                content.enqueued_at,
+                None,
+            ),
+        };
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{status}</td><td>{}</td><td>{}</td></tr>",
+            task.get_uid(),
+            escape(index.as_deref().unwrap_or_default()),
+            task_type_name(update_type),
+            enqueued_at.format(&Rfc3339).unwrap_or_default(),
+            escape(error.unwrap_or_default()),
+        );
+    }
+    body.push_str("</tbody>\n</table>\n</section>\n");
+}
+
+/// Writes the document counts of the corpus snapshot.
+async fn write_documents(body: &mut String, client: &Client, guard: &Guard, corpus: &CorpusCache, config: &Config) {
+    body.push_str("<section>\n<h2>Documents</h2>\n");
+    let corpus = match corpus.get(client, guard, config).await {
+        Ok(corpus) => corpus,
+        Err(e) => {
+            let _ = writeln!(
+                body,
+                "<p>Could not count the documents: {}</p>\n</section>",
+                escape(&e.to_string())
+            );
+            return;
+        },
+    };
+
+    let statistics = corpus.statistics.summary();
+    let _ = writeln!(
+        body,
+        "<p>{} documents, {} bytes of text, published from {} to {}. Index last updated at {}.</p>",
+        statistics.documents,
+        statistics.content_size,
+        statistics.oldest_date.as_deref().unwrap_or("?"),
+        statistics.newest_date.as_deref().unwrap_or("?"),
+        statistics.last_update.as_deref().unwrap_or("?"),
+    );
+
+    body.push_str("<table>\n<thead><tr><th>Category</th><th>Documents</th></tr></thead>\n<tbody>\n");
+    for (category, count) in &statistics.by_category {
+        let _ = writeln!(body, "<tr><td>{category}</td><td>{count}</td></tr>");
+    }
+    body.push_str("</tbody>\n</table>\n");
+
+    body.push_str("<table>\n<thead><tr><th>Year</th><th>Documents</th></tr></thead>\n<tbody>\n");
+    for (year, count) in statistics.by_year.iter().rev() {
+        let _ = writeln!(body, "<tr><td>{year}</td><td>{count}</td></tr>");
+    }
+    body.push_str("</tbody>\n</table>\n</section>\n");
+}
+
+/// Writes a ranking of queries of the analytics report.
+fn write_ranking(body: &mut String, title: &str, queries: &[QueryCount]) {
+    let _ = writeln!(body, "<h3>{title}</h3>");
+    if queries.is_empty() {
+        body.push_str("<p>None.</p>\n");
+        return;
+    }
+    body.push_str("<table>\n<thead><tr><th>Query</th><th>Searches</th><th>Average hits</th><th>Clicks</th></tr></thead>\n<tbody>\n");
+    for query in queries {
+        let _ = writeln!(
+            body,
+            "<tr><td>{}</td><td>{}</td><td>{:.1}</td><td>{}</td></tr>",
+            escape(&query.query),
+            query.searches,
+            query.average_hits,
+            query.clicks
+        );
+    }
+    body.push_str("</tbody>\n</table>\n");
+}
+
+/// Writes the search analytics of the last days.
+async fn write_analytics(body: &mut String, analytics: Option<&Analytics>) {
+    body.push_str("<section>\n<h2>Search analytics</h2>\n");
+    let Some(analytics) = analytics else {
+        body.push_str("<p>Analytics are not available.</p>\n</section>\n");
+        return;
+    };
+
+    let to = Utc::now().date_naive();
+    let from = to
+        .checked_sub_days(Days::new(DEFAULT_REPORT_DAYS))
+        .unwrap_or(NaiveDate::MIN);
+    let report = match analytics.report(from, to, REPORT_LIMIT).await {
+        Ok(report) => report,
+        Err(e) => {
+            let _ = writeln!(
+                body,
+                "<p>Could not build the report: {}</p>\n</section>",
+                escape(&e.to_string())
+            );
+            return;
+        },
+    };
+
+    let _ = writeln!(
+        body,
+        "<p>From {} to {}: {} searches, {:.1}% of them followed by a click.</p>",
+        report.from,
+        report.to,
+        report.searches,
+        report.click_through_rate * 100.0
+    );
+    write_ranking(body, "Most frequent queries", &report.top_queries);
+    write_ranking(body, "Queries without results", &report.zero_result_queries);
+    body.push_str("</section>\n");
+}
+
+/// Writes the form looking for documents to edit and, when a search was made, its results.
+async fn write_document_search(body: &mut String, q: Option<&str>, client: &Client, guard: &Guard, config: &Config) {
+    let q = q.map(str::trim).filter(|q| !q.is_empty());
+    let _ = write!(
+        body,
+        r#"<section>
+<h2>Edit a document</h2>
+<form class="search" action="/admin" method="get" role="search">
+    <p><label for="q">Search</label> <input type="search" id="q" name="q" value="{}"> <button type="submit">Search</button></p>
+</form>
+"#,
+        escape(q.unwrap_or_default()),
+    );
+
+    if let Some(q) = q {
+        let collection = match admin::admin_collection(config) {
+            Ok(collection) => collection,
+            Err(e) => {
+                let _ = writeln!(body, "<p>Could not search: {}</p>\n</section>", escape(&e.to_string()));
+                return;
+            },
+        };
+        let index = client.index(collection.index);
+        let mut search = index.search();
+        search.with_query(q).with_limit(DOCUMENT_RESULTS);
+        match guard.call(|| search.execute::<PDFdoc>()).await {
+            Ok(results) if results.hits.is_empty() => body.push_str("<p>No documents found.</p>\n"),
+            Ok(results) => {
+                body.push_str("<ul>\n");
+                for hit in &results.hits {
+                    let _ = writeln!(
+                        body,
+                        r#"<li><a href="/admin/documents/{}">{}</a> ({})</li>"#,
+                        escape(&hit.result.id),
+                        escape(&hit.result.title),
+                        hit.result.formatted_date()
+                    );
+                }
+                body.push_str("</ul>\n");
+            },
+            Err(e) => {
+                let _ = writeln!(body, "<p>Could not search: {}</p>", escape(&e.to_string()));
+            },
+        }
+    }
+    body.push_str("</section>\n");
+}
+
+/// Shows the state of the system and the entry points to the admin actions. Each section is
+/// built on its own, so that the page still shows what it can while Meilisearch is unavailable.
+pub async fn overview(
+    req: HttpRequest,
+    query: web::Query<OverviewQuery>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    corpus: web::Data<CorpusCache>,
+    config: web::Data<Config>,
+    analytics: web::Data<Option<Analytics>>,
+) -> Result<HttpResponse, Error> {
+    let admin = authenticate(&req, &config)?;
+
+    let mut body = format!(
+        "<header>\n<h1>Admin dashboard</h1>\n<p>Signed in as {}.</p>\n</header>\n<main>\n",
+        escape(&admin)
+    );
+    if let Some(task) = query.reindexed {
+        let _ = writeln!(
+            body,
+            r#"<p role="status">Re-indexing started as task {task}, its progress is shown in the recent tasks.</p>"#
+        );
+    }
+
+    write_health(&mut body, &client, &guard, &config).await;
+    write_tasks(&mut body, &client, &guard).await;
+    write_documents(&mut body, &client, &guard, &corpus, &config).await;
+    write_analytics(&mut body, analytics.as_ref().as_ref()).await;
+    write_document_search(&mut body, query.q.as_deref(), &client, &guard, &config).await;
+
+    let _ = write!(
+        body,
+        r#"<section>
+<h2>Re-index</h2>
+<p>Loads the documents of <code>{}</code>, written by Document_Parser, into the index, replacing those with the same ids.</p>
+<form action="/admin/reindex" method="post">
+    <p><button type="submit">Re-index documents</button></p>
+</form>
+</section>
+</main>"#,
+        escape(&config.admin.entries_file.display().to_string()),
+    );
+
+    Ok(html_response("Admin dashboard", &body))
+}
+
+/// Starts a re-indexing from the dashboard, see [`admin::reindex`].
+pub async fn reindex(
+    req: HttpRequest,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let admin = authenticate(&req, &config)?;
+    check_origin(&req, &config)?;
+    let task = admin::reindex(&admin, &client, &config).await?;
+
+    Ok(see_other(&format!("/admin?reindexed={}", task.task_uid)))
+}
+
+/// Query parameters of the document edit page.
+#[derive(Deserialize, Debug)]
+pub struct EditQuery {
+    /// Whether the changes were just saved.
+    #[serde(default)]
+    saved: bool,
+    /// Whether the changes were sent to Meilisearch, which has not applied them yet.
+    #[serde(default)]
+    pending: bool,
+}
+
+/// Shows a form to correct the metadata of a document.
+pub async fn edit(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<EditQuery>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    authenticate(&req, &config)?;
+    let collection = admin::admin_collection(&config)?;
+    let document = fetch_document(&client, &guard, &collection, &path.into_inner()).await?;
+    let selected = |category: i32| {
+        if document.is_normative == category {
+            " selected"
+        } else {
+            ""
+        }
+    };
+
+    let mut body = String::from(
+        "<header>\n<h1>Edit a document</h1>\n<p><a href=\"/admin\">Back to the dashboard</a></p>\n</header>\n<main>\n",
+    );
+    if query.saved {
+        body.push_str("<p role=\"status\">The changes were saved.</p>\n");
+    }
+    if query.pending {
+        body.push_str("<p role=\"status\">The changes will be saved once Meilisearch applies them.</p>\n");
+    }
+    let _ = write!(
+        body,
+        r#"<p><a href="/documents/{id}/view">Read the document</a> · <a href="/documents/{id}/pdf">View PDF</a></p>
+<form class="search" action="/admin/documents/{id}" method="post">
+    <p><label for="title">Title</label> <input type="text" id="title" name="title" value="{title}" required size="60"></p>
+    <p><label for="date">Date</label> <input type="date" id="date" name="date" value="{date}"></p>
+    <p><label for="is_normative">Category</label> <select id="is_normative" name="is_normative">
+        <option value="1"{normative}>{normative_label}</option>
+        <option value="2"{deliberative}>{deliberative_label}</option>
+        <option value="3"{unspecified}>{unspecified_label}</option>
+    </select></p>
+    <p><label for="link">Link</label> <input type="url" id="link" name="link" value="{link}" size="60"></p>
+    <p><button type="submit">Save</button></p>
+</form>
+</main>"#,
+        id = escape(&document.id),
+        title = escape(&document.title),
+        date = document.iso_date(),
+        normative = selected(1),
+        normative_label = Locale::En.category_label(1),
+        deliberative = selected(2),
+        deliberative_label = Locale::En.category_label(2),
+        unspecified = selected(3),
+        unspecified_label = Locale::En.category_label(3),
+        link = escape(&document.link),
+    );
+
+    Ok(html_response(&format!("{} - Admin dashboard", document.title), &body))
+}
+
+/// Fields of the document edit form.
+#[derive(Deserialize, Debug)]
+pub struct DocumentForm {
+    title: String,
+    /// Publication date, as YYYY-MM-DD.
+    date: String,
+    is_normative: i32,
+    link: String,
+}
+
+/// Saves the corrections made on the edit page, see [`admin::update_document`].
+pub async fn save(
+    req: HttpRequest,
+    path: web::Path<String>,
+    form: web::Form<DocumentForm>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    database: web::Data<Option<MySqlPool>>,
+) -> Result<HttpResponse, Error> {
+    let admin = authenticate(&req, &config)?;
+    check_origin(&req, &config)?;
+    let id = path.into_inner();
+    let form = form.into_inner();
+
+    // The form only has the day, so the date is left as it is unless another day was picked
+    let collection = admin::admin_collection(&config)?;
+    let original = fetch_document(&client, &guard, &collection, &id).await?;
+    let date = match form.date.trim() {
+        date if date == original.iso_date() => None,
+        "" => Some(0),
+        date => Some(
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| actix_web::error::ErrorBadRequest(format!("Invalid date: {date}, expected YYYY-MM-DD")))?
+                .and_hms_opt(0, 0, 0)
+                .unwrap_or_default()
+                .and_utc()
+                .timestamp(),
+        ),
+    };
+    let patch = DocumentPatch {
+        title: Some(form.title),
+        date,
+        is_normative: Some(form.is_normative),
+        link: Some(form.link),
+    };
+    let (_, settled) = admin::patch_document(&admin, &id, patch, &client, &guard, &config, &database).await?;
+    let status = match settled {
+        Settled::Applied => "saved",
+        Settled::Pending { .. } => "pending",
+    };
+
+    // Document ids are SHA-256 hashes, so they need no escaping
+    Ok(see_other(&format!("/admin/documents/{id}?{status}=true")))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Admin, Config};
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::http::{StatusCode, header};
+    use actix_web::test::{TestRequest, call_and_read_body, call_service};
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use std::collections::HashMap;
+    use std::fs;
+
+    /// Id of the fixture `Resolução CAD nº 45/2020 - Progressão de carreira docente`.
+    const DOCUMENT_ID: &str = "ba31fcd0803ec0051801a25f8be93868b4af2de850fee27ae1e036be2351fab9";
+
+    /// Address the server is reached at, which forms must be posted from.
+    const BASE_URL: &str = "https://mds.example.org";
+
+    fn config(name: &str) -> Config {
+        Config {
+            public_base_url: Some(BASE_URL.to_string()),
+            admin: Admin {
+                tokens: HashMap::from([("secret".to_string(), "ana".to_string())]),
+                audit_log: std::env::temp_dir().join(format!("dashboard-audit-{name}-{}.jsonl", std::process::id())),
+                ..Admin::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    /// Adds the Basic credentials a browser sends, with any user name and the token as password.
+    fn basic(request: TestRequest, password: &str) -> TestRequest {
+        let credentials = STANDARD.encode(format!("admin:{password}"));
+        request.insert_header((header::AUTHORIZATION, format!("Basic {credentials}")))
+    }
+
+    /// Returns the edit form of the fixture filled with another title and category.
+    fn form(request: TestRequest) -> TestRequest {
+        request.uri(&format!("/admin/documents/{DOCUMENT_ID}")).set_form([
+            ("title", "Resolução CAD nº 45/2020 - Progressão docente"),
+            ("date", "2020-06-01"),
+            ("is_normative", "2"),
+            (
+                "link",
+                "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1002&key=c3d4",
+            ),
+        ])
+    }
+
+    #[actix_rt::test]
+    async fn dashboard_asks_for_credentials() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock, config("credentials"));
+        let uri = format!("/admin/documents/{DOCUMENT_ID}");
+
+        for request in [
+            TestRequest::get().uri("/admin"),
+            TestRequest::get().uri(&uri),
+            basic(TestRequest::get().uri(&uri), "wrong"),
+            form(TestRequest::post()).insert_header((header::ORIGIN, BASE_URL)),
+        ] {
+            let response = call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                r#"Basic realm="Admin dashboard", charset="UTF-8""#
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn forms_from_other_sites_are_rejected() {
+        let mock = MockMeilisearch::start();
+        let config = config("origin");
+        let audit_log = config.admin.audit_log.clone();
+        let app = test_app!(mock, config);
+
+        for request in [
+            form(TestRequest::post()).insert_header((header::ORIGIN, "https://evil.example.com")),
+            form(TestRequest::post()).insert_header((header::ORIGIN, "https://mds.example.org.evil.example.com")),
+            form(TestRequest::post()).insert_header((header::REFERER, "https://evil.example.com/admin")),
+            form(TestRequest::post()).insert_header((header::REFERER, "https://mds.example.org.evil.example.com/")),
+            form(TestRequest::post()),
+            TestRequest::post()
+                .uri("/admin/reindex")
+                .insert_header((header::ORIGIN, "https://evil.example.com")),
+        ] {
+            let response = call_service(&app, basic(request, "secret").to_request()).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        assert!(!audit_log.exists());
+    }
+
+    #[actix_rt::test]
+    async fn corrections_are_saved_from_the_edit_page() {
+        let mock = MockMeilisearch::start();
+        let config = config("save");
+        let audit_log = config.admin.audit_log.clone();
+        let _ = fs::remove_file(&audit_log);
+        let app = test_app!(mock, config);
+        let uri = format!("/admin/documents/{DOCUMENT_ID}");
+
+        let body = call_and_read_body(&app, basic(TestRequest::get().uri(&uri), "secret").to_request()).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains(r#"value="Resolução CAD nº 45/2020 - Progressão de carreira docente""#));
+        assert!(body.contains(r#"value="2020-06-01""#));
+        assert!(!body.contains("The changes were saved."));
+
+        let request = form(TestRequest::post()).insert_header((header::REFERER, format!("{BASE_URL}{uri}")));
+        let response = call_service(&app, basic(request, "secret").to_request()).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap().to_str().unwrap(),
+            format!("{uri}?saved=true")
+        );
+
+        let request = basic(TestRequest::get().uri(&format!("{uri}?saved=true")), "secret");
+        let body = call_and_read_body(&app, request.to_request()).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("The changes were saved."));
+        assert!(body.contains(r#"value="Resolução CAD nº 45/2020 - Progressão docente""#));
+        assert!(body.contains(r#"<option value="2" selected>"#));
+
+        // The day was left as it was, so only the title and the category were changed
+        let audit = fs::read_to_string(&audit_log).unwrap();
+        let entry: serde_json::Value = serde_json::from_str(audit.trim()).unwrap();
+        assert_eq!(entry["admin"], "ana");
+        let changed: Vec<&String> = entry["changes"].as_object().unwrap().keys().collect();
+        assert_eq!(changed, ["is_normative", "title"]);
+        fs::remove_file(audit_log).unwrap();
+    }
+}
@@ -0,0 +1,498 @@
+use crate::api::v1::{Document, Match, Matches, SearchResponse};
+use crate::collections::{self, Collection};
+use crate::config::Config;
+use crate::corpus::{Corpus, CorpusCache};
+use crate::i18n::Locale;
+use crate::query::ParsedQuery;
+use crate::resilience::Guard;
+use crate::text::{find_matches, fold};
+use crate::{PDFdoc, caching, meilisearch_error, sitemap};
+use actix_files::NamedFile;
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue, TryIntoHeaderValue,
+};
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use log::info;
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::errors::{ErrorCode, MeilisearchError};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Number of related documents returned when the request does not ask for a specific amount.
+const DEFAULT_RELATED_LIMIT: usize = 5;
+
+/// Upper bound for the number of related documents returned by a single request.
+const MAX_RELATED_LIMIT: usize = 20;
+
+/// Number of distinctive terms of a document used to look for related documents.
+const RELATED_QUERY_TERMS: usize = 10;
+
+/// Number of characters of the document shown on each side of a match.
+const MATCH_CONTEXT_LENGTH: usize = 80;
+
+/// Query parameters shared by the pages and endpoints of a single document. Documents outside the
+/// default collection are named by their collection as well as their id.
+#[derive(Deserialize, Debug)]
+pub struct DocumentQuery {
+    pub collection: Option<String>,
+}
+
+/// Query parameters of the related documents endpoint.
+#[derive(Deserialize, Debug)]
+pub struct RelatedQuery {
+    collection: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Query parameters of the document reader view.
+#[derive(Deserialize, Debug)]
+pub struct ViewQuery {
+    collection: Option<String>,
+    q: Option<String>,
+}
+
+/// Query parameters of the in-document search.
+#[derive(Deserialize, Debug)]
+pub struct MatchesQuery {
+    collection: Option<String>,
+    q: String,
+}
+
+/// Returns the path of a page of a document, `view` or `pdf`, with the terms to highlight if any.
+/// The collection is only named outside the default collection, so that the addresses of the
+/// documents of the default collection do not change.
+pub fn document_path(config: &Config, collection: Option<&str>, id: &str, page: &str, q: Option<&str>) -> String {
+    let mut parameters = Vec::new();
+    if let Some(collection) = collection.filter(|collection| *collection != config.default_collection) {
+        parameters.push(("collection", collection));
+    }
+    if let Some(q) = q {
+        parameters.push(("q", q));
+    }
+
+    match serde_urlencoded::to_string(parameters).unwrap_or_default().as_str() {
+        "" => format!("/documents/{id}/{page}"),
+        query => format!("/documents/{id}/{page}?{query}"),
+    }
+}
+
+/// Fetches a single document of a collection from Meilisearch by its id. Returns a not found error
+/// if there is no such document, or an error if the request fails, see [`Guard::call`].
+pub async fn fetch_document(
+    client: &Client,
+    guard: &Guard,
+    collection: &Collection<'_>,
+    id: &str,
+) -> Result<PDFdoc, Error> {
+    let index = client.index(collection.index);
+    guard
+        .call_with(
+            || index.get_document::<PDFdoc>(id),
+            |e| match e {
+                meilisearch_sdk::errors::Error::Meilisearch(MeilisearchError {
+                    error_code: ErrorCode::DocumentNotFound,
+                    ..
+                }) => actix_web::error::ErrorNotFound("Document not found"),
+                e => meilisearch_error(e),
+            },
+        )
+        .await
+}
+
+/// Hashes the folded content of a document, so that exact duplicates indexed under different ids
+/// (e.g. the same PDF with a slightly different title) can be told apart from related documents.
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    fold(content).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Builds the query used to look for documents related to the given one, made of its most
+/// distinctive terms. Meilisearch drops the last terms first when nothing matches all of them, so
+/// they are ordered by decreasing weight.
+fn related_query(corpus: &Corpus, document: &PDFdoc) -> String {
+    let text = format!("{}\n{}", document.title, document.content);
+    corpus.distinctive_terms(&text, RELATED_QUERY_TERMS).join(" ")
+}
+
+/// Returns the documents of the same collection most similar to the one with the given id,
+/// excluding the document itself and any exact duplicates. The optional `limit` parameter sets how
+/// many documents are returned, and the documents are labelled with their category in the language
+/// requested, if any.
+pub async fn related(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<RelatedQuery>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    corpus: web::Data<CorpusCache>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_RELATED_LIMIT)
+        .clamp(1, MAX_RELATED_LIMIT);
+
+    let collection = collections::resolve_one(&config, params.collection.as_deref())?;
+    let document = fetch_document(&client, &guard, &collection, &id).await?;
+    let corpus = corpus.get(&client, &guard, &config).await?;
+
+    let query = related_query(&corpus, &document);
+    if query.is_empty() {
+        return Ok(HttpResponse::Ok().json(SearchResponse { results: vec![] }));
+    }
+
+    // Ask for extra hits, since the document itself and its duplicates are filtered out
+    let index = client.index(collection.index);
+    let mut search = index.search();
+    search.with_query(&query).with_limit(limit * 2 + 1);
+    let search_results = guard.call(|| search.execute::<PDFdoc>()).await?;
+
+    let mut seen_contents = HashSet::from([content_hash(&document.content)]);
+    let results: Vec<PDFdoc> = search_results
+        .hits
+        .into_iter()
+        .map(|hit| hit.result)
+        .filter(|hit| hit.id != id && seen_contents.insert(content_hash(&hit.content)))
+        .take(limit)
+        .collect();
+
+    // The results depend on the whole corpus, so the ETag is computed from the response itself
+    let locale = Locale::requested(&req);
+    let body = serde_json::to_string(&SearchResponse {
+        results: results
+            .iter()
+            .map(|result| Document::from(result).localized(locale))
+            .collect(),
+    })?;
+    Ok(caching::respond(
+        &req,
+        caching::etag(&body),
+        config.cache_max_age,
+        || HttpResponse::Ok().content_type("application/json").body(body),
+    ))
+}
+
+/// Returns the name Document_Parser gave to the PDF behind a SIGRH download link. The parser is
+/// fed files named `{idArquivo}_{key}.pdf` and builds the link from those two parts, so they can be
+/// read back from the link's query string.
+fn local_file_name(link: &str) -> Option<String> {
+    let (_, query) = link.split_once('?')?;
+    let parameter = |name: &str| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+            .filter(|value| !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric()))
+    };
+
+    Some(format!("{}_{}.pdf", parameter("idArquivo")?, parameter("key")?))
+}
+
+/// Builds the `Content-Disposition` header for a PDF named after the document title, with an ASCII
+/// fallback for older clients and the original title encoded as UTF-8 for the others.
+fn pdf_disposition(title: &str) -> ContentDisposition {
+    let file_name: String = title
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_control() || "/\\:*?\"<>|".contains(c) {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let file_name = if file_name.is_empty() {
+        "documento".to_string()
+    } else {
+        file_name
+    };
+    let ascii_name: String = file_name
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .map(|c| if c.is_ascii() { c } else { '_' })
+        .collect();
+
+    ContentDisposition {
+        disposition: DispositionType::Inline,
+        parameters: vec![
+            DispositionParam::Filename(format!("{ascii_name}.pdf")),
+            DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+                value: format!("{file_name}.pdf").into_bytes(),
+            }),
+        ],
+    }
+}
+
+/// Serves the PDF of the document with the given id from the local mirror of processed files,
+/// which supports range requests and conditional requests. If the file is not in the mirror, the
+/// client is redirected to the original SIGRH link instead.
+pub async fn pdf(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<DocumentQuery>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let collection = collections::resolve_one(&config, params.collection.as_deref())?;
+    let document = fetch_document(&client, &guard, &collection, &path.into_inner()).await?;
+
+    if let Some(file_name) = local_file_name(&document.link) {
+        match NamedFile::open_async(config.pdf_dir.join(&file_name)).await {
+            Ok(file) => {
+                let mut response = file
+                    .set_content_disposition(pdf_disposition(&document.title))
+                    .into_response(&req);
+                response.headers_mut().insert(
+                    header::CACHE_CONTROL,
+                    caching::cache_control(config.cache_max_age).try_into_value()?,
+                );
+                return Ok(response);
+            },
+            Err(e) => info!("PDF {file_name} not found in the local mirror ({e}), redirecting."),
+        }
+    }
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, document.link))
+        .finish())
+}
+
+/// Renders the text of the document with the given id as an HTML page, highlighting the terms of
+/// the optional `q` query parameter, written in the same syntax as searches. The page is written in
+/// the language requested, English by default.
+pub async fn view(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<ViewQuery>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let collection = collections::resolve_one(&config, params.collection.as_deref())?;
+    let document = fetch_document(&client, &guard, &collection, &path.into_inner()).await?;
+    let locale = Locale::requested(&req).unwrap_or_default();
+    let canonical_url = sitemap::document_url(
+        &sitemap::base_url(&req, &config),
+        &config,
+        Some(collection.name),
+        &document.id,
+    );
+    let pdf_path = document_path(&config, Some(collection.name), &document.id, "pdf", None);
+    let terms = params
+        .q
+        .as_deref()
+        .map(|q| ParsedQuery::parse(q).terms)
+        .unwrap_or_default();
+
+    // The page only depends on the document, the terms and the locale, so it is not rendered again
+    // for clients that already have it
+    let etag = caching::etag(&(
+        &document.id,
+        &document.title,
+        document.date,
+        &document.content,
+        document.is_normative,
+        &terms,
+        &canonical_url,
+        locale.tag(),
+    ));
+    Ok(caching::respond(&req, etag, config.cache_max_age, || {
+        HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(crate::reader::render(
+                &document,
+                &terms,
+                &canonical_url,
+                &pdf_path,
+                locale,
+            ))
+    }))
+}
+
+/// Collapses runs of whitespace, including line breaks and form feeds, into a single space.
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Finds every occurrence of the folded terms in the content of a document, with the character
+/// offsets of the match, the page it starts on and the text around it.
+fn locate_matches<'a>(content: &'a str, terms: &[String]) -> Vec<Match<'a>> {
+    let mut matches = Vec::new();
+    // The matches are sorted, so offsets and pages are counted from the end of the previous one
+    let (mut byte_offset, mut char_offset, mut page) = (0, 0, 1);
+
+    for range in find_matches(content, terms) {
+        let skipped = &content[byte_offset..range.start];
+        char_offset += skipped.chars().count();
+        page += skipped.matches('\u{c}').count();
+        byte_offset = range.start;
+
+        let text = &content[range.clone()];
+        let before_start = content[..range.start]
+            .char_indices()
+            .rev()
+            .nth(MATCH_CONTEXT_LENGTH - 1)
+            .map_or(0, |(start, _)| start);
+        let after_end = content[range.end..]
+            .char_indices()
+            .nth(MATCH_CONTEXT_LENGTH)
+            .map_or(content.len(), |(end, _)| range.end + end);
+
+        matches.push(Match {
+            text,
+            start: char_offset,
+            end: char_offset + text.chars().count(),
+            page,
+            before: collapse_whitespace(&content[before_start..range.start]),
+            after: collapse_whitespace(&content[range.end..after_end]),
+        });
+    }
+
+    matches
+}
+
+/// Returns every occurrence of the terms of the `q` query parameter in the document with the given
+/// id, written in the same syntax as searches, so that long documents can be browsed match by
+/// match. Each match has its offsets in the content of the document, counted in characters, the
+/// page of the PDF it is on and the text around it.
+pub async fn matches(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<MatchesQuery>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let collection = collections::resolve_one(&config, params.collection.as_deref())?;
+    let document = fetch_document(&client, &guard, &collection, &path.into_inner()).await?;
+    let terms = ParsedQuery::parse(&params.q).terms;
+
+    let etag = caching::etag(&(&document.id, &document.content, &terms));
+    Ok(caching::respond(&req, etag, config.cache_max_age, || {
+        HttpResponse::Ok().json(Matches {
+            q: &params.q,
+            matches: locate_matches(&document.content, &terms),
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_meilisearch::{self, MockMeilisearch};
+    use actix_web::App;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use std::fs;
+
+    /// Id of the document whose link is `downloadArquivo?idArquivo=1001&key=a1b2`.
+    const MIRRORED_ID: &str = "e6abb4cd2d06313a64ed60c32c6442008b3530e753cdaadfb888d5cd72184fff";
+
+    /// Id of the document whose link is `downloadArquivo?idArquivo=1002&key=c3d4`.
+    const UNMIRRORED_ID: &str = "ba31fcd0803ec0051801a25f8be93868b4af2de850fee27ae1e036be2351fab9";
+
+    #[test]
+    fn test_local_file_name() {
+        assert_eq!(
+            local_file_name("https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001&key=a1b2").as_deref(),
+            Some("1001_a1b2.pdf")
+        );
+        assert_eq!(
+            local_file_name("https://sig.unb.br/sigrh/downloadArquivo?key=a1b2&x=1&idArquivo=7").as_deref(),
+            Some("7_a1b2.pdf")
+        );
+
+        // Anything that could leave the mirror directory is refused
+        for link in [
+            "https://sig.unb.br/sigrh/downloadArquivo",
+            "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001",
+            "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001&key=",
+            "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=../1001&key=a1b2",
+            "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001&key=a%2Fb",
+        ] {
+            assert_eq!(local_file_name(link), None, "{link}");
+        }
+    }
+
+    #[test]
+    fn test_pdf_disposition() {
+        let disposition = pdf_disposition("  Resolução CEPE nº 12/2019: \"Trancamento\"\n");
+        assert_eq!(
+            disposition.get_filename(),
+            Some("Resolucao CEPE n_ 12-2019- -Trancamento-.pdf")
+        );
+        let Some(DispositionParam::FilenameExt(extended)) = disposition.parameters.get(1) else {
+            panic!("The UTF-8 file name should follow the ASCII one.");
+        };
+        assert_eq!(
+            extended.value,
+            "Resolução CEPE nº 12-2019- -Trancamento-.pdf".as_bytes()
+        );
+        assert!(disposition.is_inline());
+
+        assert_eq!(pdf_disposition(" ").get_filename(), Some("documento.pdf"));
+    }
+
+    #[actix_rt::test]
+    async fn pdf_is_served_from_the_mirror_or_redirected() {
+        let mock = MockMeilisearch::start();
+        let pdf_dir = std::env::temp_dir().join(format!("pdf-mirror-{}", std::process::id()));
+        fs::create_dir_all(&pdf_dir).unwrap();
+        fs::write(pdf_dir.join("1001_a1b2.pdf"), b"%PDF-1.4 test").unwrap();
+
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(Client::new(&mock.url, Some(mock_meilisearch::API_KEY))))
+                .app_data(web::Data::new(Guard::new(Default::default())))
+                .app_data(web::Data::new(Config {
+                    pdf_dir: pdf_dir.clone(),
+                    ..Config::default()
+                }))
+                .service(web::resource("/documents/{id}/pdf").route(web::get().to(pdf))),
+        )
+        .await;
+
+        let request = TestRequest::get()
+            .uri(&format!("/documents/{MIRRORED_ID}/pdf"))
+            .insert_header((header::RANGE, "bytes=0-3"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers().get(header::CONTENT_TYPE).unwrap(), "application/pdf");
+        let disposition = response
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(
+            disposition.starts_with("inline; filename=\"Resolucao CEPE n_ 12-2019 - Trancamento de matricula.pdf\"")
+        );
+        assert!(response.headers().contains_key(header::CACHE_CONTROL));
+        assert_eq!(read_body(response).await, "%PDF".as_bytes());
+
+        let request = TestRequest::get()
+            .uri(&format!("/documents/{UNMIRRORED_ID}/pdf"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1002&key=c3d4"
+        );
+
+        let request = TestRequest::get().uri("/documents/unknown/pdf").to_request();
+        assert_eq!(call_service(&app, request).await.status(), StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(pdf_dir).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+use crate::config::{Config, LiveSettings};
+use crate::i18n::Locale;
+use crate::query::ParsedQuery;
+use crate::resilience::Guard;
+use crate::{PDFdoc, SearchQueryWrapper, collections, query_meilisearch_page};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use futures_util::stream::{self, StreamExt};
+use log::info;
+use meilisearch_sdk::client::Client;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Number of documents requested from Meilisearch for each chunk of an export.
+const EXPORT_PAGE_SIZE: usize = 100;
+
+/// File formats the search results can be exported to.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Ndjson,
+    Bibtex,
+}
+
+impl ExportFormat {
+    const fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Json => "application/json",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Bibtex => "application/x-bibtex; charset=utf-8",
+        }
+    }
+
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+            Self::Bibtex => "bib",
+        }
+    }
+
+    /// Returns what is written before the first document.
+    const fn header(self) -> &'static str {
+        match self {
+            Self::Csv => "title,date,category,link\r\n",
+            Self::Json => "[",
+            Self::Ndjson | Self::Bibtex => "",
+        }
+    }
+
+    /// Returns what is written after the last document.
+    const fn footer(self) -> &'static str {
+        match self {
+            Self::Json => "]",
+            Self::Csv | Self::Ndjson | Self::Bibtex => "",
+        }
+    }
+}
+
+/// Query parameters of the export endpoint, on top of those of the search endpoint.
+#[derive(Deserialize, Debug)]
+pub struct ExportParams {
+    format: ExportFormat,
+}
+
+/// The fields of a document written to an export.
+#[derive(Serialize)]
+struct ExportRecord<'a> {
+    title: &'a str,
+    date: String,
+    category: &'static str,
+    link: &'a str,
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break. A field that a
+/// spreadsheet would read as a formula, starting with `=`, `+`, `-` or `@`, is prefixed with `'` so
+/// that it is shown as text.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        Cow::Owned(format!("'{value}"))
+    } else {
+        Cow::Borrowed(value)
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escapes the characters that have a special meaning in BibTeX field values.
+fn bibtex_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "&%$#_{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Writes a single document in the given format, with the name of its category in the given locale.
+/// `first` tells whether it is the first document of the export, since JSON arrays need separators
+/// between elements.
+fn write_record(
+    output: &mut String,
+    format: ExportFormat,
+    locale: Locale,
+    document: &PDFdoc,
+    first: bool,
+) -> Result<(), Error> {
+    let record = ExportRecord {
+        title: &document.title,
+        date: document.iso_date(),
+        category: locale.category_label(document.is_normative),
+        link: &document.link,
+    };
+
+    match format {
+        ExportFormat::Csv => {
+            let _ = write!(
+                output,
+                "{},{},{},{}\r\n",
+                csv_field(record.title),
+                record.date,
+                csv_field(record.category),
+                csv_field(record.link)
+            );
+        },
+        ExportFormat::Json | ExportFormat::Ndjson => {
+            if format == ExportFormat::Json && !first {
+                output.push(',');
+            }
+            output.push_str(&serde_json::to_string(&record)?);
+            if format == ExportFormat::Ndjson {
+                output.push('\n');
+            }
+        },
+        ExportFormat::Bibtex => {
+            // Document ids are SHA-256 hashes, so a prefix is enough to tell them apart
+            let key: String = document.id.chars().take(12).collect();
+            let _ = writeln!(output, "@misc{{unb-{key},");
+            let _ = writeln!(output, "  title = {{{{{}}}}},", bibtex_value(record.title));
+            let _ = writeln!(output, "  date = {{{}}},", record.date);
+            let _ = writeln!(output, "  year = {{{}}},", record.date.get(..4).unwrap_or_default());
+            let _ = writeln!(output, "  note = {{{}}},", record.category);
+            let _ = writeln!(output, "  url = {{{}}}", record.link);
+            let _ = writeln!(output, "}}\n");
+        },
+    }
+
+    Ok(())
+}
+
+/// State of an export while its documents are being streamed.
+struct Export {
+    client: Client,
+    guard: web::Data<Guard>,
+    index: String,
+    query: ParsedQuery,
+    format: ExportFormat,
+    locale: Locale,
+    /// Number of hits already received from Meilisearch, before those the query does not accept
+    /// were dropped. The next chunk starts there rather than from the first hit again.
+    offset: usize,
+    /// Number of documents already written.
+    written: usize,
+    max: usize,
+    finished: bool,
+}
+
+impl Export {
+    /// Fetches the next page of results and writes it in the export format. Returns `None` once
+    /// every matching document, or the configured maximum, has been written.
+    async fn next_chunk(&mut self) -> Option<Result<Bytes, Error>> {
+        if self.finished {
+            return None;
+        }
+
+        let limit = EXPORT_PAGE_SIZE.min(self.max - self.written);
+        let (search_results, received) =
+            match query_meilisearch_page(&self.query, &self.client, &self.guard, &self.index, self.offset, limit).await
+            {
+                Ok(page) => page,
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                },
+            };
+
+        // A short page means Meilisearch has no more hits, however many the query then dropped
+        self.offset += received;
+        self.finished = received < limit;
+
+        let mut chunk = String::new();
+        for hit in &search_results.hits {
+            if let Err(e) = write_record(&mut chunk, self.format, self.locale, &hit.result, self.written == 0) {
+                self.finished = true;
+                return Some(Err(e));
+            }
+            self.written += 1;
+        }
+        self.finished |= self.written >= self.max;
+
+        Some(Ok(Bytes::from(chunk)))
+    }
+}
+
+/// Exports every document matching a search, rather than a single page of results, as CSV, JSON,
+/// NDJSON or BibTeX. Accepts the same parameters as the search endpoint plus `format`, and streams
+/// the documents as they are fetched from Meilisearch, up to the configured maximum. Exports are
+/// limited to a single collection. Categories are named in the language requested, in English by
+/// default.
+pub async fn export(
+    req: HttpRequest,
+    query: web::Query<SearchQueryWrapper>,
+    params: web::Query<ExportParams>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    settings: web::Data<LiveSettings>,
+) -> Result<HttpResponse, Error> {
+    info!(
+        "Received export request with query: {query:#?} and format {:?}",
+        params.format
+    );
+
+    let index = match collections::resolve(&config, query.collection.as_deref())?.as_slice() {
+        [collection] => collection.index.to_string(),
+        _ => return Err(actix_web::error::ErrorBadRequest("Exports support a single collection")),
+    };
+    let settings = settings.get();
+    let format = params.format;
+    let query = query.parse(&settings)?.unwrap_or_default();
+    let mut export = Export {
+        client: client.get_ref().clone(),
+        guard,
+        index,
+        finished: query.is_empty() || settings.export_max == 0,
+        query,
+        format,
+        locale: Locale::requested(&req).unwrap_or_default(),
+        offset: 0,
+        written: 0,
+        max: settings.export_max,
+    };
+
+    // The first chunk is fetched before answering, so that a failing search is still reported
+    // with an error status instead of an empty export.
+    let first_chunk = export.next_chunk().await.transpose()?;
+
+    let documents = stream::iter(first_chunk.map(Ok)).chain(stream::unfold(export, |mut export| async move {
+        let chunk = export.next_chunk().await?;
+        Some((chunk, export))
+    }));
+    let body = stream::once(async move { Ok::<_, Error>(Bytes::from_static(format.header().as_bytes())) })
+        .chain(documents)
+        .chain(stream::once(async move {
+            Ok(Bytes::from_static(format.footer().as_bytes()))
+        }));
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("export.{}", format.extension()))],
+        })
+        .streaming(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::test::{TestRequest, call_and_read_body};
+
+    fn document() -> PDFdoc {
+        PDFdoc {
+            id: "e6abb4cd2d06313a64ed60c32c6442008b3530e753cdaadfb888d5cd72184fff".to_string(),
+            title: "Resolução nº 12/2019, \"Trancamento\" & 100% dos {cursos}".to_string(),
+            date: 1_554_076_800,
+            content: String::new(),
+            link: "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001&key=a1b2".to_string(),
+            is_normative: 1,
+            collection: None,
+        }
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("Normative"), "Normative");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a, b"), "\"a, b\"");
+        assert_eq!(csv_field("o \"termo\""), "\"o \"\"termo\"\"\"");
+        assert_eq!(csv_field("linha\nquebrada"), "\"linha\nquebrada\"");
+        assert_eq!(csv_field("fim\r"), "\"fim\r\"");
+        assert_eq!(
+            csv_field("=HYPERLINK(\"http://x\")"),
+            "\"'=HYPERLINK(\"\"http://x\"\")\""
+        );
+        assert_eq!(csv_field("+1"), "'+1");
+        assert_eq!(csv_field("-2+3"), "'-2+3");
+        assert_eq!(csv_field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(csv_field("Resolução nº 1-2020"), "Resolução nº 1-2020");
+    }
+
+    #[test]
+    fn test_bibtex_value() {
+        assert_eq!(bibtex_value("Resolução nº 12"), "Resolução nº 12");
+        assert_eq!(bibtex_value("R&D 100% $5 #1 a_b {x}"), r"R\&D 100\% \$5 \#1 a\_b \{x\}");
+    }
+
+    #[test]
+    fn test_write_record() {
+        let document = document();
+        let write = |format, locale, first| {
+            let mut output = String::new();
+            write_record(&mut output, format, locale, &document, first).unwrap();
+            output
+        };
+
+        assert_eq!(
+            write(ExportFormat::Csv, Locale::PtBr, true),
+            "\"Resolução nº 12/2019, \"\"Trancamento\"\" & 100% dos {cursos}\",2019-04-01,Normativa,\
+             https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001&key=a1b2\r\n"
+        );
+
+        let json = r#"{"title":"Resolução nº 12/2019, \"Trancamento\" & 100% dos {cursos}","date":"2019-04-01","category":"Normative","link":"https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001&key=a1b2"}"#;
+        assert_eq!(write(ExportFormat::Json, Locale::En, true), json);
+        assert_eq!(write(ExportFormat::Json, Locale::En, false), format!(",{json}"));
+        assert_eq!(write(ExportFormat::Ndjson, Locale::En, false), format!("{json}\n"));
+
+        assert_eq!(
+            write(ExportFormat::Bibtex, Locale::En, true),
+            "@misc{unb-e6abb4cd2d06,
+  title = {{Resolução nº 12/2019, \"Trancamento\" \\& 100\\% dos \\{cursos\\}}},
+  date = {2019-04-01},
+  year = {2019},
+  note = {Normative},
+  url = {https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1001&key=a1b2}
+}
+
+"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn export_stops_at_the_first_short_page() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        // Every document has the word "de", and all but two have "Art." in their content
+        let request = TestRequest::get()
+            .uri("/api/v1/export?q=de%20-content:art&format=csv")
+            .to_request();
+        let body = call_and_read_body(&app, request).await;
+        let rows: Vec<&str> = std::str::from_utf8(&body).unwrap().lines().collect();
+        assert_eq!(
+            rows,
+            [
+                "title,date,category,link",
+                "Ata da reunião do Conselho Universitário de 10/03/2021,2021-03-10,Deliberative,\
+                 https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1003&key=e5f6",
+                "Comunicado sobre o recadastramento de aposentados,2023-01-01,Unspecified,\
+                 https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1005&key=i9j0",
+            ]
+        );
+
+        let searches = mock.searches();
+        assert_eq!(searches.len(), 1);
+        assert_eq!(searches[0]["offset"], 0);
+        assert_eq!(searches[0]["limit"], EXPORT_PAGE_SIZE);
+    }
+
+    #[actix_rt::test]
+    async fn export_continues_from_the_last_hit_received() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(
+            mock,
+            Config {
+                settings: crate::config::Settings {
+                    export_max: 1,
+                    ..Default::default()
+                },
+                ..Config::default()
+            }
+        );
+
+        let request = TestRequest::get()
+            .uri("/api/v1/export?q=de%20-content:art&format=ndjson")
+            .to_request();
+        let body = call_and_read_body(&app, request).await;
+        assert_eq!(std::str::from_utf8(&body).unwrap().lines().count(), 1);
+
+        // The hits the query drops are not fetched again
+        let offsets: Vec<u64> = mock
+            .searches()
+            .iter()
+            .map(|search| search["offset"].as_u64().unwrap())
+            .collect();
+        assert!(offsets.windows(2).all(|pair| pair[1] == pair[0] + 1), "{offsets:?}");
+        assert!(mock.searches().iter().all(|search| search["limit"] == 1));
+    }
+}
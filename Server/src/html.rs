@@ -0,0 +1,39 @@
+use crate::i18n::Locale;
+
+/// Escapes text so that it can be safely placed inside HTML elements and double quoted attributes.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wraps the given body markup into a complete HTML page written in `locale`. The title is escaped,
+/// while `head` and `body` must already be valid, escaped markup.
+pub fn page(locale: Locale, title: &str, head: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="{lang}">
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{title}</title>
+    <link rel="stylesheet" href="/static/pages.css">
+{head}</head>
+<body>
+{body}
+</body>
+</html>
+"#,
+        lang = locale.tag(),
+        title = escape(title),
+    )
+}
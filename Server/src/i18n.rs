@@ -0,0 +1,265 @@
+//! Localization of the JSON API and of the public HTML pages. Clients ask for a language with the
+//! `lang` query parameter or the Accept-Language header, and get human-readable category labels
+//! along with the documents, error messages and pages in that language. Clients asking for nothing
+//! get the responses as they were before, in English and without labels.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{Error, HttpRequest};
+use std::future::Future;
+
+/// Languages the API is available in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    /// Brazilian Portuguese, the language of the documents.
+    PtBr,
+    /// English, the language of the API before it was localized.
+    #[default]
+    En,
+}
+
+impl Locale {
+    /// Returns the locale matching a language tag such as `pt-BR`, `pt` or `en-US`, if any. Every
+    /// variant of Portuguese is given Brazilian Portuguese.
+    fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next().unwrap_or_default();
+        if language.eq_ignore_ascii_case("pt") {
+            Some(Self::PtBr)
+        } else if language.eq_ignore_ascii_case("en") {
+            Some(Self::En)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the language tag of the locale, as sent in the Content-Language header.
+    pub const fn tag(self) -> &'static str {
+        match self {
+            Self::PtBr => "pt-BR",
+            Self::En => "en",
+        }
+    }
+
+    /// Returns the locale a request asked for: the one of the `lang` query parameter, or else the
+    /// supported language the Accept-Language header prefers. Returns `None` if the request asked
+    /// for no supported language.
+    pub fn requested(req: &HttpRequest) -> Option<Self> {
+        let lang = serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|(name, value)| (name == "lang").then(|| Self::from_tag(&value)).flatten());
+        lang.or_else(|| {
+            req.headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(Self::preferred)
+        })
+    }
+
+    /// Returns the supported language an Accept-Language header gives the highest weight to. Of
+    /// languages with the same weight, the first listed is preferred.
+    fn preferred(accept_language: &str) -> Option<Self> {
+        let mut preferred: Option<(Self, f32)> = None;
+        for range in accept_language.split(',') {
+            let mut parts = range.split(';');
+            let Some(locale) = parts.next().and_then(Self::from_tag) else {
+                continue;
+            };
+            let weight = parts
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |weight| weight.trim().parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if weight > 0.0 && !preferred.is_some_and(|(_, best)| weight <= best) {
+                preferred = Some((locale, weight));
+            }
+        }
+        preferred.map(|(locale, _)| locale)
+    }
+
+    /// Returns the name of a value of `is_normative`.
+    pub const fn category_label(self, is_normative: i32) -> &'static str {
+        match (self, is_normative) {
+            (Self::PtBr, 1) => "Normativa",
+            (Self::PtBr, 2) => "Deliberativa",
+            (Self::PtBr, _) => "Não especificada",
+            (Self::En, 1) => "Normative",
+            (Self::En, 2) => "Deliberative",
+            (Self::En, _) => "Unspecified",
+        }
+    }
+
+    /// Returns the version of a text of the pages in the locale, given in both languages.
+    pub const fn text(self, en: &'static str, pt_br: &'static str) -> &'static str {
+        match self {
+            Self::PtBr => pt_br,
+            Self::En => en,
+        }
+    }
+
+    /// Returns an error message of the server in the locale. Messages are written in English, so
+    /// `None` is returned for English and for messages without a translation.
+    pub fn translate(self, message: &str) -> Option<String> {
+        if self == Self::En {
+            return None;
+        }
+
+        if let Some(value) = message
+            .strip_prefix("Invalid date: ")
+            .and_then(|rest| rest.strip_suffix(", expected YYYY-MM-DD"))
+        {
+            return Some(format!("Data inválida: {value}, use o formato AAAA-MM-DD"));
+        }
+        if let Some(name) = message.strip_prefix("Unknown collection: ") {
+            return Some(format!("Coleção desconhecida: {name}"));
+        }
+        if let Some(details) = message.strip_prefix("Query deserialize error: ") {
+            return Some(format!("Parâmetros inválidos: {details}"));
+        }
+        if let Some(details) = message.strip_prefix("Invalid query: ") {
+            return Some(format!("Consulta inválida: {details}"));
+        }
+
+        let translation = match message {
+            "Meilisearch query failed" => "Falha na consulta ao Meilisearch",
+            "Search is temporarily unavailable" => "A busca está temporariamente indisponível",
+            "Document not found" => "Documento não encontrado",
+            "Invalid page number" => "Número de página inválido",
+            "Exports support a single collection" => "A exportação aceita uma única coleção",
+            "Analytics query failed" => "Falha na consulta das estatísticas de uso",
+            "Analytics are not available" => "As estatísticas de uso não estão disponíveis",
+            "Tenant tokens are not configured" => "Os tokens de acesso não estão configurados",
+            "A token gives access to a single collection" => "Um token dá acesso a uma única coleção",
+            "A document belongs to a single collection" => "Um documento pertence a uma única coleção",
+            "Could not generate the token" => "Não foi possível gerar o token",
+            "Invalid client token" => "Token de cliente inválido",
+            "Invalid admin token" => "Token de administrador inválido",
+            "Too many requests" => "Requisições demais",
+            "The title cannot be empty" => "O título não pode ficar vazio",
+            "The category must be 1, 2 or 3" => "A categoria deve ser 1, 2 ou 3",
+            "Database update failed" => "Falha na atualização do banco de dados",
+            "Could not read the parsed entries" => "Não foi possível ler os documentos processados",
+            "Could not parse the parsed entries" => "Não foi possível interpretar os documentos processados",
+            _ => return None,
+        };
+        Some(translation.to_string())
+    }
+}
+
+/// Localizes the responses of the API. Error messages are replaced by their translation in the
+/// locale the request asked for, keeping the status and headers, and the locale is sent in the
+/// Content-Language header. Responses vary with the Accept-Language header either way.
+pub fn localize<S, B>(req: ServiceRequest, srv: &S) -> impl Future<Output = Result<ServiceResponse, Error>> + 'static
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let locale = Locale::requested(req.request());
+    let response = srv.call(req);
+
+    async move {
+        let mut response = response.await?.map_into_boxed_body();
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("Accept-Language"));
+        let Some(locale) = locale else {
+            return Ok(response);
+        };
+        response
+            .headers_mut()
+            .insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(locale.tag()));
+
+        let translation = response
+            .response()
+            .error()
+            .and_then(|e| locale.translate(&e.to_string()));
+        Ok(match translation {
+            Some(translation) => response.map_body(|head, _| {
+                head.headers.insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("text/plain; charset=utf-8"),
+                );
+                BoxBody::new(translation)
+            }),
+            None => response,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::test::{TestRequest, call_and_read_body, call_service, read_body};
+
+    /// Returns the locale requested by a request to the given URI with the given Accept-Language
+    /// header.
+    fn requested(uri: &str, accept_language: Option<&str>) -> Option<Locale> {
+        let mut request = TestRequest::get().uri(uri);
+        if let Some(accept_language) = accept_language {
+            request = request.insert_header((header::ACCEPT_LANGUAGE, accept_language));
+        }
+        Locale::requested(&request.to_http_request())
+    }
+
+    #[test]
+    fn test_requested_locale() {
+        assert_eq!(requested("/", None), None);
+        assert_eq!(requested("/?lang=pt-BR", None), Some(Locale::PtBr));
+        assert_eq!(requested("/?lang=pt_PT", None), Some(Locale::PtBr));
+        assert_eq!(requested("/?q=x&lang=EN-us", None), Some(Locale::En));
+
+        // The parameter wins over the header, unless it names an unsupported language
+        assert_eq!(requested("/?lang=en", Some("pt-BR")), Some(Locale::En));
+        assert_eq!(requested("/?lang=fr", Some("pt-BR")), Some(Locale::PtBr));
+        assert_eq!(requested("/?lang=fr", None), None);
+
+        assert_eq!(requested("/", Some("fr-FR, pt;q=0.8, en;q=0.9")), Some(Locale::En));
+        assert_eq!(requested("/", Some("en;q=0.5, pt-BR")), Some(Locale::PtBr));
+        assert_eq!(requested("/", Some("pt, en")), Some(Locale::PtBr));
+        assert_eq!(requested("/", Some("en;q=0, pt;q=0")), None);
+        assert_eq!(requested("/", Some("de, *")), None);
+    }
+
+    #[test]
+    fn test_text_and_labels() {
+        assert_eq!(Locale::En.text("Next page", "Próxima página"), "Next page");
+        assert_eq!(Locale::PtBr.text("Next page", "Próxima página"), "Próxima página");
+        assert_eq!(Locale::default(), Locale::En);
+
+        assert_eq!(Locale::PtBr.category_label(2), "Deliberativa");
+        assert_eq!(Locale::En.category_label(7), "Unspecified");
+        assert_eq!(
+            Locale::PtBr.translate("Unknown collection: atas").as_deref(),
+            Some("Coleção desconhecida: atas")
+        );
+        assert_eq!(Locale::En.translate("Document not found"), None);
+    }
+
+    #[actix_rt::test]
+    async fn results_page_is_rendered_in_portuguese() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let request = TestRequest::get()
+            .uri("/buscar?q=trancamento")
+            .insert_header((header::ACCEPT_LANGUAGE, "pt-BR,pt;q=0.9,en;q=0.8"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.headers().get(header::CONTENT_LANGUAGE).unwrap(), "pt-BR");
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.contains(r#"<html lang="pt-BR">"#));
+        assert!(body.contains("<h1>Busca de documentos</h1>"));
+        assert!(body.contains("Resultados 1 a 1 para “trancamento”."));
+        assert!(body.contains(" · Normativa"));
+        assert!(body.contains("Ver PDF"));
+        assert!(!body.contains("View PDF"));
+
+        // Pages are in English unless another language is asked for
+        let request = TestRequest::get().uri("/buscar?q=perspicaz").to_request();
+        let body = String::from_utf8(call_and_read_body(&app, request).await.to_vec()).unwrap();
+        assert!(body.contains(r#"<html lang="en">"#));
+        assert!(body.contains("No documents found for “perspicaz”."));
+    }
+}
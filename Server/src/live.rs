@@ -0,0 +1,403 @@
+//! Live search over a WebSocket. The page sends the query as it is typed, and only the results of
+//! the latest one are pushed back: a search still running when a new query arrives is cancelled,
+//! so Meilisearch is not kept busy with queries nobody will see. Each query counts towards the rate
+//! limit of the client, and none is recorded in the analytics, since most are only partly typed.
+
+use crate::api::v1::{Document, LiveError, LiveResults};
+use crate::config::{Config, LiveSettings};
+use crate::i18n::Locale;
+use crate::rate_limit::{self, RateLimiter};
+use crate::resilience::Guard;
+use crate::{SEARCH_LIMIT, SearchQueryWrapper, run_search};
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{CloseCode, Codec, Frame, Item, Message, hash_key, verify_handshake};
+use actix_web::http::header;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use futures_util::{StreamExt, stream};
+use log::{debug, warn};
+use meilisearch_sdk::client::Client;
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+use tokio::task::JoinHandle;
+
+/// Number of messages waiting to be sent to the page before the connection stops reading queries,
+/// so that a page that does not read its results cannot make the server buffer them.
+const MESSAGE_BUFFER: usize = 8;
+
+/// Largest query message accepted, once its fragments are put back together.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Shared state the searches of a connection need.
+#[derive(Clone)]
+struct Searcher {
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    settings: web::Data<LiveSettings>,
+    limiter: Option<web::Data<RateLimiter>>,
+    /// Address the rate limit of the client is counted by, taken when opening the connection.
+    client_address: String,
+    /// Language the page asked for when opening the connection.
+    locale: Option<Locale>,
+}
+
+impl Searcher {
+    /// Returns an error message in the language of the connection.
+    fn message(&self, message: String) -> String {
+        self.locale
+            .and_then(|locale| locale.translate(&message))
+            .unwrap_or(message)
+    }
+
+    /// Sends an error to the page, for the given query.
+    async fn send_error(&self, q: &str, message: String, sender: &Sender<Message>) {
+        let error = LiveError {
+            q,
+            error: self.message(message),
+        };
+        if let Ok(text) = serde_json::to_string(&error) {
+            drop(sender.send(Message::Text(text.into())).await);
+        }
+    }
+
+    /// Counts a query towards the rate limit of the client and tells whether it is within it.
+    fn allowed(&self) -> bool {
+        self.limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.check(&self.client_address, self.settings.get().rate_limit))
+    }
+
+    /// Runs a search and sends its results, or the reason it failed, to the page.
+    async fn search(self, query: SearchQueryWrapper, sender: Sender<Message>) {
+        let searched = run_search(
+            &query,
+            &self.client,
+            &self.guard,
+            &self.config,
+            &self.settings.get(),
+            None,
+            0,
+            SEARCH_LIMIT,
+        )
+        .await;
+
+        let text = match searched {
+            Ok(searched) => serde_json::to_string(&LiveResults {
+                q: &query.q,
+                results: searched
+                    .hits
+                    .iter()
+                    .map(|hit| Document::from(&hit.result).localized(self.locale))
+                    .collect(),
+                event: searched.event,
+            }),
+            Err(e) => serde_json::to_string(&LiveError {
+                q: &query.q,
+                error: self.message(e.to_string()),
+            }),
+        };
+        match text {
+            Ok(text) => drop(sender.send(Message::Text(text.into())).await),
+            Err(e) => warn!("Could not serialize live search results: {e}"),
+        }
+    }
+}
+
+/// Handles a query sent by the page: the search of the previous one is cancelled if it is still
+/// running, and one is started for this query unless the client is over its rate limit.
+async fn start_search(
+    text: &[u8],
+    searcher: &Searcher,
+    sender: &Sender<Message>,
+    pending: &mut Option<JoinHandle<()>>,
+) {
+    let query: SearchQueryWrapper = match serde_json::from_slice(text) {
+        Ok(query) => query,
+        Err(e) => return searcher.send_error("", format!("Invalid query: {e}"), sender).await,
+    };
+    if let Some(previous) = pending.take() {
+        previous.abort();
+    }
+    if !searcher.allowed() {
+        return searcher
+            .send_error(&query.q, "Too many requests".to_string(), sender)
+            .await;
+    }
+    *pending = Some(actix_rt::spawn(searcher.clone().search(query, sender.clone())));
+}
+
+/// Reads the frames sent by the page until the connection is closed. Fragmented messages are put
+/// back together before being handled, and binary messages are ignored.
+async fn session(mut payload: web::Payload, sender: Sender<Message>, searcher: Searcher) {
+    let mut codec = Codec::new();
+    let mut buffer = BytesMut::new();
+    let mut pending: Option<JoinHandle<()>> = None;
+    // Fragments of the message being received, and whether it is a text message
+    let mut fragments: Option<(bool, BytesMut)> = None;
+
+    'connection: while let Some(chunk) = payload.next().await {
+        let Ok(chunk) = chunk else {
+            break;
+        };
+        buffer.extend_from_slice(&chunk);
+
+        loop {
+            let frame = match codec.decode(&mut buffer) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("Closing live search connection after a protocol error: {e}");
+                    drop(sender.send(Message::Close(Some(CloseCode::Protocol.into()))).await);
+                    break 'connection;
+                },
+            };
+
+            let close = match frame {
+                Frame::Text(text) => {
+                    start_search(&text, &searcher, &sender, &mut pending).await;
+                    None
+                },
+                Frame::Continuation(item) => match (item, fragments.as_mut()) {
+                    (Item::FirstText(bytes), None) => {
+                        fragments = Some((true, BytesMut::from(&bytes[..])));
+                        None
+                    },
+                    (Item::FirstBinary(bytes), None) => {
+                        fragments = Some((false, BytesMut::from(&bytes[..])));
+                        None
+                    },
+                    (Item::Continue(bytes), Some((_, message))) => {
+                        message.extend_from_slice(&bytes);
+                        (message.len() > MAX_MESSAGE_SIZE).then_some(CloseCode::Size)
+                    },
+                    (Item::Last(bytes), Some(_)) => {
+                        let (is_text, mut message) = fragments.take().unwrap_or_default();
+                        message.extend_from_slice(&bytes);
+                        if message.len() > MAX_MESSAGE_SIZE {
+                            Some(CloseCode::Size)
+                        } else {
+                            if is_text {
+                                start_search(&message, &searcher, &sender, &mut pending).await;
+                            }
+                            None
+                        }
+                    },
+                    // A fragment out of sequence
+                    _ => Some(CloseCode::Protocol),
+                },
+                Frame::Ping(bytes) => {
+                    drop(sender.send(Message::Pong(bytes)).await);
+                    None
+                },
+                Frame::Close(reason) => {
+                    drop(sender.send(Message::Close(reason)).await);
+                    break 'connection;
+                },
+                Frame::Binary(_) | Frame::Pong(_) => None,
+            };
+            if let Some(code) = close {
+                debug!("Closing live search connection: {code:?}");
+                drop(sender.send(Message::Close(Some(code.into()))).await);
+                break 'connection;
+            }
+        }
+    }
+
+    if let Some(pending) = pending {
+        pending.abort();
+    }
+}
+
+/// Encodes the messages for the page as WebSocket frames, ending the stream after a close frame.
+fn frames(receiver: Receiver<Message>) -> impl futures_util::Stream<Item = Result<Bytes, Error>> {
+    stream::unfold(
+        (receiver, Codec::new(), false),
+        |(mut receiver, mut codec, closed)| async move {
+            if closed {
+                return None;
+            }
+            let message = receiver.recv().await?;
+            let closing = matches!(message, Message::Close(_));
+            let mut buffer = BytesMut::new();
+            let encoded = codec
+                .encode(message, &mut buffer)
+                .map(|()| buffer.freeze())
+                .map_err(Error::from);
+            Some((encoded, (receiver, codec, closing)))
+        },
+    )
+}
+
+/// Upgrades the request to a WebSocket on which the page sends queries as JSON objects with the
+/// same fields as the parameters of the search endpoint, and receives the results of the latest
+/// one. Results and errors are localized in the language asked for when opening the connection.
+pub async fn live_search(
+    req: HttpRequest,
+    payload: web::Payload,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    settings: web::Data<LiveSettings>,
+) -> Result<HttpResponse, Error> {
+    verify_handshake(req.head())?;
+    let key = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_KEY)
+        .map(|key| hash_key(key.as_bytes()))
+        .unwrap_or_default();
+
+    let (sender, receiver) = channel(MESSAGE_BUFFER);
+    let searcher = Searcher {
+        client,
+        guard,
+        limiter: req.app_data::<web::Data<RateLimiter>>().cloned(),
+        client_address: rate_limit::client_address(&req, &config.trusted_proxies),
+        config,
+        settings,
+        locale: Locale::requested(&req),
+    };
+    actix_rt::spawn(session(payload, sender, searcher));
+
+    Ok(HttpResponse::SwitchingProtocols()
+        .upgrade("websocket")
+        .insert_header((header::SEC_WEBSOCKET_ACCEPT, key.as_slice()))
+        .streaming(frames(receiver)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RateLimit;
+    use crate::mock_meilisearch::{self, MockMeilisearch};
+    use actix_http::ws;
+    use actix_web::App;
+    use futures_util::SinkExt;
+    use serde_json::{Value, json};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    /// Returns the next message pushed on the connection, or `None` if none comes in time.
+    async fn next_message<S>(connection: &mut S, wait: Duration) -> Option<Value>
+    where
+        S: futures_util::Stream<Item = Result<ws::Frame, ws::ProtocolError>> + Unpin,
+    {
+        match actix_rt::time::timeout(wait, connection.next()).await {
+            Ok(Some(Ok(ws::Frame::Text(text)))) => Some(serde_json::from_slice(&text).unwrap()),
+            Ok(frame) => panic!("Unexpected frame: {frame:?}"),
+            Err(_) => None,
+        }
+    }
+
+    /// Starts a server answering live searches on `/api/v1/search/live` with the given settings.
+    fn start_server(mock: &MockMeilisearch, config: Config) -> actix_test::TestServer {
+        let client = web::Data::new(Client::new(&mock.url, Some(mock_meilisearch::API_KEY)));
+        let guard = web::Data::new(Guard::new(config.resilience.clone()));
+        let settings = web::Data::new(LiveSettings::new(config.settings.clone()));
+        let limiter = web::Data::new(RateLimiter::default());
+        let config = web::Data::new(config);
+        actix_test::start(move || {
+            App::new()
+                .app_data(client.clone())
+                .app_data(guard.clone())
+                .app_data(settings.clone())
+                .app_data(limiter.clone())
+                .app_data(config.clone())
+                .service(web::resource("/api/v1/search/live").route(web::get().to(live_search)))
+        })
+    }
+
+    fn query(q: &str) -> ws::Message {
+        ws::Message::Text(json!({ "q": q }).to_string().into())
+    }
+
+    #[actix_rt::test]
+    async fn only_the_latest_query_is_answered() {
+        let mock = MockMeilisearch::start();
+        let mut server = start_server(&mock, Config::default());
+        let mut connection = server.ws_at("/api/v1/search/live").await.unwrap();
+
+        // The first search is still running when the second query is typed
+        mock.delay_searches(Duration::from_millis(300));
+        connection.send(query("trancamento")).await.unwrap();
+        while mock.searches().is_empty() {
+            actix_rt::time::sleep(Duration::from_millis(10)).await;
+        }
+        connection.send(query("aposentados")).await.unwrap();
+
+        let message = next_message(&mut connection, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(message["q"], "aposentados");
+        let titles: Vec<&str> = message["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["Comunicado sobre o recadastramento de aposentados"]);
+        assert_eq!(next_message(&mut connection, Duration::from_millis(600)).await, None);
+        let queries: Vec<Value> = mock.searches().into_iter().map(|search| search["q"].clone()).collect();
+        assert_eq!(queries, ["trancamento", "aposentados"]);
+
+        // Queries that are not JSON objects are answered with an error
+        mock.delay_searches(Duration::ZERO);
+        connection.send(ws::Message::Text("trancamento".into())).await.unwrap();
+        let message = next_message(&mut connection, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(message["q"], "");
+        assert!(message["error"].as_str().unwrap().starts_with("Invalid query: "));
+
+        connection.send(ws::Message::Close(None)).await.unwrap();
+        assert!(matches!(connection.next().await, Some(Ok(ws::Frame::Close(None)))));
+    }
+
+    #[actix_rt::test]
+    async fn each_query_counts_towards_the_rate_limit() {
+        let mock = MockMeilisearch::start();
+        let mut config = Config::default();
+        config.settings.rate_limit = RateLimit {
+            requests: 2,
+            window: 60,
+        };
+        let mut server = start_server(&mock, config);
+        let mut connection = server.ws_at("/api/v1/search/live").await.unwrap();
+
+        for q in ["trancamento", "aposentados"] {
+            connection.send(query(q)).await.unwrap();
+            let message = next_message(&mut connection, Duration::from_secs(5)).await.unwrap();
+            assert_eq!(message["q"], q);
+            assert!(message["results"].is_array());
+        }
+        connection.send(query("servidores")).await.unwrap();
+        let message = next_message(&mut connection, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(message, json!({ "q": "servidores", "error": "Too many requests" }));
+        assert_eq!(mock.searches().len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn fragmented_queries_are_put_back_together() {
+        let mock = MockMeilisearch::start();
+        let mut server = start_server(&mock, Config::default());
+        let mut connection = server.ws_at("/api/v1/search/live").await.unwrap();
+
+        let text = json!({ "q": "aposentados" }).to_string();
+        let (first, last) = text.split_at(8);
+        for item in [
+            ws::Item::FirstText(Bytes::copy_from_slice(first.as_bytes())),
+            ws::Item::Continue(Bytes::new()),
+            ws::Item::Last(Bytes::copy_from_slice(last.as_bytes())),
+        ] {
+            connection.send(ws::Message::Continuation(item)).await.unwrap();
+        }
+        let message = next_message(&mut connection, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(message["q"], "aposentados");
+        assert_eq!(message["results"].as_array().unwrap().len(), 1);
+
+        // A fragment continuing no message breaks the protocol. The client codec refuses to send
+        // one, so it is written as it is
+        let mut frame = BytesMut::new();
+        ws::Parser::write_message(&mut frame, b"", ws::OpCode::Continue, true, true);
+        connection.io_mut().write_all(&frame).await.unwrap();
+        match connection.next().await {
+            Some(Ok(ws::Frame::Close(Some(reason)))) => assert_eq!(reason.code, CloseCode::Protocol),
+            frame => panic!("Unexpected frame: {frame:?}"),
+        }
+    }
+}
@@ -1,14 +1,87 @@
+mod admin;
+mod analytics;
+mod api;
+mod caching;
+mod collections;
+mod config;
+mod corpus;
+mod cors;
+mod dashboard;
+mod documents;
+mod export;
+mod html;
+mod i18n;
+mod live;
+#[cfg(test)]
+mod mock_meilisearch;
+mod query;
+mod rate_limit;
+mod reader;
+mod references;
+mod resilience;
+mod results;
+mod sitemap;
+mod stats;
+mod text;
+mod tls;
+mod tokens;
+
 use actix_files::{Files, NamedFile};
-use actix_web::{web, App, Error, HttpResponse, HttpServer};
+use actix_web::http::header;
+use actix_web::middleware::{Compress, DefaultHeaders};
+use actix_web::web::Bytes;
+use actix_web::{App, Error, HttpRequest, HttpResponse, HttpServer, web};
+use analytics::Analytics;
+use chrono::NaiveDate;
+use config::{Config, LiveSettings, Settings};
+use corpus::CorpusCache;
+use futures_util::future;
+use futures_util::stream::{self, Stream, StreamExt};
+use i18n::Locale;
+use log::{LevelFilter, error, info};
 use meilisearch_sdk::client::Client;
-use rayon::prelude::*;
+use meilisearch_sdk::search::SearchResult;
+use query::{ParsedQuery, SortOrder};
+use rate_limit::RateLimiter;
+use resilience::Guard;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 
 /// Wrapper for the search query.
 #[derive(Deserialize, Debug)]
 struct SearchQueryWrapper {
     q: String,
+    /// Collections to search, see [`collections::resolve`].
+    collection: Option<String>,
+    /// Order of the results, by relevance unless given.
+    #[serde(default)]
+    sort: SortOrder,
+    /// First day of publication of the documents to search, as YYYY-MM-DD.
+    from: Option<String>,
+    /// Last day of publication of the documents to search, as YYYY-MM-DD.
+    to: Option<String>,
+}
+
+impl SearchQueryWrapper {
+    /// Prepares the query with [`prepare_query`] and adds the order and the publication dates
+    /// requested. Fails with a bad request error if a date is invalid.
+    fn parse(&self, settings: &Settings) -> Result<Option<ParsedQuery>, Error> {
+        let day = |value: Option<&str>| match value.map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|_| actix_web::error::ErrorBadRequest(format!("Invalid date: {value}, expected YYYY-MM-DD"))),
+        };
+        let date_filter = query::date_range_filter(day(self.from.as_deref())?, day(self.to.as_deref())?);
+
+        Ok(prepare_query(&self.q, settings).map(|mut parsed_query| {
+            parsed_query.sort = self.sort;
+            parsed_query.filters.extend(date_filter);
+            parsed_query
+        }))
+    }
 }
 
 /// Represents the fields of each object in the database.
@@ -20,117 +93,1040 @@ struct PDFdoc {
     content: String,
     link: String,
     is_normative: i32,
+    /// Name of the collection the document was found in. Not stored in the index, only set on
+    /// search results.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    collection: Option<String>,
+}
+impl PDFdoc {
+    /// Returns the document date formatted as DD/MM/YYYY, or an empty string if it is invalid.
+    fn formatted_date(&self) -> String {
+        chrono::NaiveDateTime::from_timestamp_opt(self.date, 0)
+            .map(|date| date.format("%d/%m/%Y").to_string())
+            .unwrap_or_default()
+    }
+
+    /// Returns the document date as an ISO 8601 date (YYYY-MM-DD), or an empty string if it is
+    /// invalid.
+    fn iso_date(&self) -> String {
+        chrono::NaiveDateTime::from_timestamp_opt(self.date, 0)
+            .map(|date| date.date().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Logs a Meilisearch error and turns it into an internal server error for the client.
+fn meilisearch_error(e: meilisearch_sdk::errors::Error) -> Error {
+    error!("Meilisearch Error: {e:?}");
+    actix_web::error::ErrorInternalServerError("Meilisearch query failed")
 }
-/// Wraper for the server response
-#[derive(Serialize)]
-struct SearchResponse {
-    results: Vec<PDFdoc>,
+
+/// Number of results returned by a search request.
+const SEARCH_LIMIT: usize = 20;
+
+/// Number of hits fetched at once when the hits of a search are checked against the query.
+const CHECKED_BATCH_SIZE: usize = 100;
+
+/// Normalizes the raw query string with [`query::normalize`], cutting it to the configured maximum
+/// length, and parses it. Returns `None` if the query has fewer characters than the configured
+/// minimum or has nothing to search for.
+fn prepare_query(query: &str, settings: &Settings) -> Option<ParsedQuery> {
+    let normalized_query = query::normalize(query, settings.max_query_length);
+    if normalized_query.chars().count() < settings.min_query_length {
+        return None;
+    }
+
+    // Parse the advanced query syntax
+    Some(ParsedQuery::parse(&normalized_query)).filter(|parsed_query| !parsed_query.is_empty())
 }
 
-/// Performs a Meilisearch query based on the provided query string and the Meilisearch client.
-/// Returns Meilisearch search results, or an internal server error if the query fails.
+/// Performs a Meilisearch query on the given index based on the provided parsed query and the
+/// Meilisearch client, returning at most `limit` hits starting at `offset`. Phrases, exclusions and
+/// field restrictions are sent along with the query string and the shortcuts as filters, while
+/// field-scoped terms are checked on the returned hits. The hits are then fetched in batches from
+/// the first one, so that the page is cut from the accepted hits only and is full unless the
+/// results run out. Returns Meilisearch search results, or an error if the query fails, see
+/// [`Guard::call`].
 async fn query_meilisearch(
-    query: &str,
+    query: &ParsedQuery,
     client: &Client,
+    guard: &Guard,
+    index: &str,
+    offset: usize,
+    limit: usize,
 ) -> Result<meilisearch_sdk::search::SearchResults<PDFdoc>, Error> {
-    let search_results = client
-        .index("entries")
-        .search()
-        .with_query(query)
-        .execute()
-        .await
-        .map_err(|e| {
-            eprintln!("Meilisearch Error: {e:?}");
-            actix_web::error::ErrorInternalServerError("Meilisearch query failed")
-        })?;
-
-    Ok(search_results)
-}
-
-/// This function serializes the search results since it does not implement the Serialize and
-/// Deserialize traits. It receives a `SearchResults` Struct and returns a JSON formatted string
-/// with the vector of results.
-fn serialize_search_results(search_results: &meilisearch_sdk::search::SearchResults<PDFdoc>) -> String {
-    let entries: Vec<PDFdoc> = search_results
-        .hits
-        .par_iter()
-        .map(|hit| PDFdoc {
-            id: hit.result.id.clone(),
-            title: hit.result.title.clone(),
-            date: hit.result.date,
-            content: hit.result.content.clone(),
-            link: hit.result.link.clone(),
-            is_normative: hit.result.is_normative,
-        })
-        .collect();
-
-    let search_response = SearchResponse { results: entries };
-
-    serde_json::to_string(&search_response).expect("Could not serialize search results.")
+    if !query.checks_hits() {
+        let (search_results, _) = query_meilisearch_page(query, client, guard, index, offset, limit).await?;
+        return Ok(search_results);
+    }
+
+    let wanted = offset.saturating_add(limit);
+    let mut accepted = Vec::new();
+    let mut fetched = 0;
+    loop {
+        let batch = wanted.saturating_sub(accepted.len()).max(CHECKED_BATCH_SIZE);
+        let (mut search_results, received) =
+            query_meilisearch_page(query, client, guard, index, fetched, batch).await?;
+        fetched += received;
+        accepted.append(&mut search_results.hits);
+
+        if received < batch || accepted.len() >= wanted {
+            search_results.hits = accepted.into_iter().skip(offset).take(limit).collect();
+            return Ok(search_results);
+        }
+    }
 }
 
-/// The main search function. Listens for JSON requests with a search query and returns a JSON
-/// response.
-async fn search(query: web::Query<SearchQueryWrapper>, client: web::Data<Client>) -> Result<HttpResponse, Error> {
-    println!("Received search request with query: {query:#?}");
+/// Fetches the hits Meilisearch ranks from `raw_offset` to `raw_offset + limit` for the parsed
+/// query, keeping only those the query accepts when it has field-scoped terms. Returns the kept
+/// hits along with the number of hits Meilisearch returned, which is less than `limit` once its
+/// results run out, or an error if the query fails, see [`Guard::call`].
+async fn query_meilisearch_page(
+    query: &ParsedQuery,
+    client: &Client,
+    guard: &Guard,
+    index: &str,
+    raw_offset: usize,
+    limit: usize,
+) -> Result<(meilisearch_sdk::search::SearchResults<PDFdoc>, usize), Error> {
+    let index = client.index(index);
+    let filters: Vec<&str> = query.filters.iter().map(String::as_str).collect();
+    let sort = query.sort.expression().map(|expression| [expression]);
 
-    // Trim the query to the first 200 characters
-    let trimmed_query = if query.q.len() > 200 { &query.q[..200] } else { &query.q };
+    let mut search = index.search();
+    search
+        .with_query(&query.text)
+        .with_show_ranking_score(true)
+        .with_offset(raw_offset)
+        .with_limit(limit);
+    if !query.attributes.is_empty() {
+        search.with_attributes_to_search_on(&query.attributes);
+    }
+    if !filters.is_empty() {
+        search.with_array_filter(filters);
+    }
+    if let Some(sort) = &sort {
+        search.with_sort(sort);
+    }
 
-    if trimmed_query.len() < 3 {
-        // You can adjust the minimum query length
-        return Ok(HttpResponse::Ok().json(SearchResponse { results: vec![] }));
+    let mut search_results = guard.call(|| search.execute::<PDFdoc>()).await?;
+    let received = search_results.hits.len();
+    if query.checks_hits() {
+        search_results
+            .hits
+            .retain(|hit| query.accepts(&hit.result.title, &hit.result.content));
     }
+    Ok((search_results, received))
+}
+
+/// Serializes the hit at `position` as an element of the `results` array of the search response,
+/// preceded by a separator unless it is the first.
+fn serialize_hit(hits: &[SearchResult<PDFdoc>], position: usize, locale: Option<Locale>) -> Result<Bytes, Error> {
+    let mut buffer = Vec::new();
+    if position > 0 {
+        buffer.push(b',');
+    }
+    let document = api::v1::Document::from(&hits[position].result).localized(locale);
+    serde_json::to_writer(&mut buffer, &document).map_err(|e| {
+        error!("Could not serialize a search result: {e}");
+        actix_web::error::ErrorInternalServerError("Could not serialize the search results")
+    })?;
+    Ok(Bytes::from(buffer))
+}
+
+/// Streams the JSON response of the search endpoint, as defined by the v1 schema, serializing the
+/// documents one at a time straight from the hits, labelled with their category in the given
+/// locale. The first hit is serialized before answering, so that a failure is still reported with
+/// an error status; later ones end the response early.
+fn search_response_body(
+    hits: Arc<[SearchResult<PDFdoc>]>,
+    locale: Option<Locale>,
+) -> Result<impl Stream<Item = Result<Bytes, Error>> + 'static, Error> {
+    let first = hits.first().map(|_| serialize_hit(&hits, 0, locale)).transpose()?;
+    let rest = stream::iter(1..hits.len()).map(move |position| serialize_hit(&hits, position, locale));
+
+    Ok(stream::once(future::ok(Bytes::from_static(b"{\"results\":[")))
+        .chain(stream::iter(first.map(Ok)))
+        .chain(rest)
+        .chain(stream::once(future::ok(Bytes::from_static(b"]}")))))
+}
+
+/// Hits of a search run by [`run_search`].
+struct Searched {
+    /// Shared with the results kept for when Meilisearch is unavailable.
+    hits: Arc<[SearchResult<PDFdoc>]>,
+    /// Id of the analytics event of the search.
+    event: Option<i64>,
+    /// Whether the hits are those of an earlier identical search, served because Meilisearch is
+    /// unavailable.
+    stale: bool,
+}
+
+/// Runs a search on the requested collections and records it for the analytics. Returns at most
+/// `limit` hits starting at `offset`, merged and re-ranked when several collections are requested,
+/// and the id of the analytics event. Queries too short to be searched return no hits and are not
+/// recorded, nor are the later pages of a search. While Meilisearch is unavailable, the latest hits
+/// of the same search are returned if they were kept.
+#[allow(clippy::too_many_arguments)]
+async fn run_search(
+    query: &SearchQueryWrapper,
+    client: &Client,
+    guard: &Guard,
+    config: &Config,
+    settings: &Settings,
+    analytics: Option<&Analytics>,
+    offset: usize,
+    limit: usize,
+) -> Result<Searched, Error> {
+    let started = Instant::now();
+
+    let collections = collections::resolve(config, query.collection.as_deref())?;
 
-    // Query Meilisearch
-    let search_results = query_meilisearch(trimmed_query, &client).await?;
+    let Some(parsed_query) = query.parse(settings)? else {
+        return Ok(Searched {
+            hits: Arc::new([]),
+            event: None,
+            stale: false,
+        });
+    };
 
-    // Serialize the results to JSON
-    let search_results_json = serialize_search_results(&search_results);
+    // Query Meilisearch. Meilisearch ignores case and accents, so the results are kept under the
+    // folded query
+    let key = resilience::key(&(
+        text::fold(&query::normalize(&query.q, settings.max_query_length)),
+        &query.collection,
+        query.sort,
+        &query.from,
+        &query.to,
+        offset,
+        limit,
+    ));
+    let (hits, stale) =
+        match collections::federated_search(&parsed_query, client, guard, &collections, offset, limit).await {
+            Ok(hits) => {
+                let hits: Arc<[SearchResult<PDFdoc>]> = hits.into();
+                guard.remember(key, &hits);
+                (hits, false)
+            },
+            Err(e) if resilience::is_unavailable(&e) => (guard.stale(key).ok_or(e)?, true),
+            Err(e) => return Err(e),
+        };
 
-    Ok(HttpResponse::Ok()
-        .content_type("application/json")
-        .body(search_results_json))
+    let mut event = None;
+    if let Some(analytics) = analytics.filter(|_| offset == 0) {
+        let names: Vec<&str> = collections.iter().map(|collection| collection.name).collect();
+        event = analytics
+            .record(
+                &query.q,
+                settings.max_query_length,
+                hits.len(),
+                &parsed_query.filters,
+                &names,
+                started.elapsed(),
+            )
+            .await;
+    }
+
+    Ok(Searched { hits, event, stale })
+}
+
+/// The main search function. Listens for JSON requests with a search query and returns a JSON
+/// response. The id of the analytics event of the search is sent in a header, and results kept
+/// from an earlier search are marked with a `Warning` header.
+async fn search(
+    req: HttpRequest,
+    query: web::Query<SearchQueryWrapper>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    settings: web::Data<LiveSettings>,
+    analytics: web::Data<Option<Analytics>>,
+) -> Result<HttpResponse, Error> {
+    info!("Received search request with query: {query:#?}");
+
+    let searched = run_search(
+        &query,
+        &client,
+        &guard,
+        &config,
+        &settings.get(),
+        analytics.as_ref().as_ref(),
+        0,
+        SEARCH_LIMIT,
+    )
+    .await?;
+
+    // Stream the results as JSON
+    let body = search_response_body(searched.hits, Locale::requested(&req))?;
+
+    let mut response = HttpResponse::Ok();
+    if let Some(event) = searched.event {
+        response.insert_header((analytics::EVENT_HEADER, event));
+    }
+    if searched.stale {
+        response.insert_header((header::WARNING, "110 - \"Response is Stale\""));
+    }
+
+    Ok(response.content_type("application/json").streaming(body))
 }
 
 /// Serves the main webpage.
 fn index() -> Result<NamedFile, Error> {
     let path: PathBuf = PathBuf::from("static/index.html");
-    println!("Serving index.html from path: {path:?}");
+    info!("Serving index.html from path: {path:?}");
     Ok(NamedFile::open(path)?)
 }
 
+/// Registers the routes of the server. The application data they rely on is added by the caller,
+/// so that tests can provide their own.
+fn configure(cfg: &mut web::ServiceConfig, cache_max_age: u32) {
+    cfg.service(
+        web::scope(api::V1_PREFIX)
+            .configure(api::configure_v1)
+            .wrap_fn(rate_limit::limit)
+            .wrap_fn(i18n::localize),
+    )
+    // Tokens are also issued at the path trusted frontends were first given
+    .service(
+        web::resource("/api/tokens")
+            .wrap_fn(rate_limit::limit)
+            .wrap_fn(i18n::localize)
+            .route(web::post().to(tokens::issue)),
+    )
+    // Registered before the dashboard, whose scope would take `/admin/analytics`
+    .configure(api::configure_legacy)
+    .service(
+        web::resource("/buscar")
+            .wrap_fn(rate_limit::limit)
+            .wrap_fn(i18n::localize)
+            .route(web::get().to(results::results_page)),
+    )
+    .service(web::resource("/sitemap.xml").route(web::get().to(sitemap::index)))
+    .service(web::resource("/sitemaps/{number}.xml").route(web::get().to(sitemap::sitemap)))
+    .service(web::resource("/sitemaps/{collection}/{number}.xml").route(web::get().to(sitemap::collection_sitemap)))
+    .service(web::resource("/robots.txt").route(web::get().to(sitemap::robots)))
+    .service(web::resource("/ready").route(web::get().to(resilience::ready)))
+    .service(web::resource("/metrics").route(web::get().to(resilience::metrics)))
+    .service(web::scope("/admin").configure(dashboard::configure))
+    .service(web::resource("/documents/{id}/pdf").route(web::get().to(documents::pdf)))
+    .service(
+        web::resource("/documents/{id}/view")
+            .wrap_fn(i18n::localize)
+            .route(web::get().to(documents::view)),
+    )
+    .service(
+        web::scope("/static")
+            .wrap(DefaultHeaders::new().add(caching::cache_control(cache_max_age)))
+            .service(Files::new("", "static").show_files_listing()),
+    )
+    .route("/", web::get().to(|| async { index() }))
+    .default_service(web::route().to(HttpResponse::NotFound));
+}
+
 /// The entry point of the program. Sets up the Actix-web server, connects to the Meilisearch
 /// server, and starts the server.
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
+    // Everything is passed to the logger, which filters with the maximum level set from the
+    // settings, so that the level can be changed on reload
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Trace)
+        .format_timestamp_secs()
+        .init();
+    log::set_max_level(LevelFilter::Info);
+
     let api_key = std::env::var("MEILISEARCH_API_KEY").expect("missing MEILISEARCH_API_KEY environment variable.");
 
+    let config = Config::load();
+
     //Uses the SDK to connect to the Meilisearch server. For the prototype I hardcoded the API key
-    let meilisearch_client = Client::new("http://localhost:7700", Some(api_key));
+    let meilisearch_client = Client::new(&config.meilisearch_url, Some(api_key));
+
+    // The `ano:` and `tipo:` query shortcuts and the date range are translated into filters on
+    // these attributes
+    for index in config.collections.values() {
+        if let Err(e) = meilisearch_client
+            .index(index)
+            .set_filterable_attributes(["date", "is_normative"])
+            .await
+        {
+            error!("Could not update the filterable attributes of {index}: {e:?}");
+        }
+        // Results can be sorted by date instead of relevance
+        if let Err(e) = meilisearch_client.index(index).set_sortable_attributes(["date"]).await {
+            error!("Could not update the sortable attributes of {index}: {e:?}");
+        }
+    }
+    config
+        .settings
+        .apply(None, &meilisearch_client, config.collections.values())
+        .await;
 
     let meilisearch_client_data = web::Data::new(meilisearch_client.clone());
+    let corpus_data = web::Data::new(CorpusCache::default());
+    let config_data = web::Data::new(config.clone());
+    // The connection is only opened when the admin API first needs it
+    let database_data = web::Data::new(
+        config
+            .database_url
+            .as_deref()
+            .map(|url| sqlx::MySqlPool::connect_lazy(url).expect("Invalid database_url in the config file.")),
+    );
+    let settings_data = web::Data::new(LiveSettings::new(config.settings.clone()));
+    let rate_limiter_data = web::Data::new(RateLimiter::default());
+    let guard_data = web::Data::new(Guard::new(config.resilience.clone()));
+    let analytics_data = web::Data::new(Analytics::open(&config.analytics_database).await);
+
+    config::watch(settings_data.clone(), config.clone(), meilisearch_client.clone());
 
     let server = HttpServer::new(move || {
         App::new()
             .app_data(meilisearch_client_data.clone()) // Share the client across requests
-            .service(web::resource("/search").to(search))
-            .service(Files::new("/static", "static").show_files_listing())
-            .route("/", web::get().to(|| async { index() }))
-            .default_service(web::route().to(HttpResponse::NotFound))
+            .app_data(guard_data.clone())
+            .app_data(corpus_data.clone())
+            .app_data(config_data.clone())
+            .app_data(database_data.clone())
+            .app_data(analytics_data.clone())
+            .app_data(settings_data.clone())
+            .app_data(rate_limiter_data.clone())
+            .configure(|cfg| configure(cfg, config_data.cache_max_age))
+            // Preflight requests are answered before reaching the rate limit
+            .wrap_fn(cors::cors)
+            .wrap(Compress::default())
     });
 
-    let server = server.bind("127.0.0.1:8080")?;
-    println!("Actix-web server started at http://127.0.0.1:8080");
-    server.run().await
+    // On SIGTERM or SIGINT, actix stops accepting connections and waits up to the shutdown timeout
+    // for in-flight requests to finish
+    let server = server.shutdown_timeout(config.shutdown_timeout);
+
+    let Some(tls_config) = config.tls.clone() else {
+        let server = server.bind(&config.bind_address)?;
+        info!("Actix-web server started at http://{}", config.bind_address);
+        return server.run().await;
+    };
+
+    let server = server.bind_rustls_021(&config.bind_address, tls::server_config(tls_config.clone())?)?;
+    info!("Actix-web server started at https://{}", config.bind_address);
+
+    let Some(redirect_address) = tls_config.redirect_address else {
+        return server.run().await;
+    };
+    let https_port = server.addrs().first().map_or(443, std::net::SocketAddr::port);
+    future::try_join(
+        server.run(),
+        tls::run_redirect(&redirect_address, https_port, config.shutdown_timeout),
+    )
+    .await
+    .map(|_| ())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use actix_web::test;
+    use mock_meilisearch::{MockMeilisearch, test_app};
     use std::env;
 
+    /// Sends a search to the application and returns the titles of the results.
+    macro_rules! search_titles {
+        ($app:expr, $query:expr) => {{
+            let uri = format!(
+                "/api/v1/search?{}",
+                serde_urlencoded::to_string([("q", $query)]).unwrap()
+            );
+            let response: serde_json::Value =
+                test::call_and_read_body_json(&$app, test::TestRequest::get().uri(&uri).to_request()).await;
+            response["results"]
+                .as_array()
+                .expect("The results should be an array.")
+                .iter()
+                .map(|result| result["title"].as_str().unwrap_or_default().to_string())
+                .collect::<Vec<String>>()
+        }};
+    }
+
+    #[actix_rt::test]
+    async fn search_returns_matching_documents() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let titles = search_titles!(app, "trancamento");
+        assert_eq!(titles, ["Resolução CEPE nº 12/2019 - Trancamento de matrícula"]);
+
+        // Case and accents are ignored
+        let titles = search_titles!(app, "FERIAS");
+        assert_eq!(titles, ["Instrução Normativa DGP nº 3/2022 - Férias de servidores"]);
+
+        let titles = search_titles!(app, "perspicaz");
+        assert!(titles.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn search_results_have_every_field() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/search?q=aposentados")
+            .to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        let result = &response["results"][0];
+        assert_eq!(result["title"], "Comunicado sobre o recadastramento de aposentados");
+        assert_eq!(result["date"], 1_672_531_200);
+        assert_eq!(result["is_normative"], 3);
+        assert_eq!(result["collection"], "sigrh");
+        assert!(result["content"].as_str().unwrap().contains("recadastramento anual"));
+        assert!(
+            result["link"]
+                .as_str()
+                .unwrap()
+                .starts_with("https://sig.unb.br/sigrh/")
+        );
+    }
+
+    #[actix_rt::test]
+    async fn search_ignores_queries_under_three_characters() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        for query in ["", "a", "ab", "çã", "  a\u{0}b  "] {
+            assert!(search_titles!(app, query).is_empty());
+        }
+        assert!(
+            mock.searches().is_empty(),
+            "Short queries should not reach Meilisearch."
+        );
+
+        assert!(search_titles!(app, "ata").len() <= SEARCH_LIMIT);
+        assert_eq!(mock.searches().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn search_truncates_queries_to_200_characters() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let query = "servidores ".repeat(30);
+        let titles = search_titles!(app, &query);
+        assert_eq!(titles, ["Instrução Normativa DGP nº 3/2022 - Férias de servidores"]);
+
+        // The last word is cut, and Meilisearch matches it as a prefix
+        let searches = mock.searches();
+        assert_eq!(searches[0]["q"], query[..200]);
+        assert!(searches[0]["q"].as_str().unwrap().ends_with(" se"));
+
+        // Characters are counted rather than bytes, and one straddling the limit is not split
+        let query = format!("{}ção", "a".repeat(199));
+        let _ = search_titles!(app, &query);
+        let searches = mock.searches();
+        assert_eq!(searches[1]["q"], format!("{}ç", "a".repeat(199)));
+    }
+
+    #[actix_rt::test]
+    async fn search_sends_shortcuts_as_filters() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        // The stand-in does not apply filters, so only what was sent is checked
+        let titles = search_titles!(app, "ano:2021 calendario");
+        assert_eq!(titles, ["Ata da reunião do Conselho Universitário de 10/03/2021"]);
+
+        let searches = mock.searches();
+        assert_eq!(searches[0]["q"], "calendario");
+        assert_eq!(searches[0]["filter"][0], "date >= 1609459200 AND date < 1640995200");
+    }
+
+    #[actix_rt::test]
+    async fn search_excludes_whole_words() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let titles = search_titles!(app, "progressao -docente");
+        assert_eq!(
+            titles,
+            ["Resolução CAD nº 8/2023 - Progressão de carreira dos técnicos-administrativos"]
+        );
+        assert_eq!(mock.searches()[0]["q"], "progressao -docente");
+
+        // "Data" and "matrícula" do not contain the word "ata"
+        let titles = search_titles!(app, "trancamento -content:ata");
+        assert_eq!(titles, ["Resolução CEPE nº 12/2019 - Trancamento de matrícula"]);
+        let titles = search_titles!(app, "title:CAD carreira");
+        assert_eq!(titles.len(), 2);
+        let titles = search_titles!(app, "comunicado title:CAD");
+        assert!(titles.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn checked_searches_return_full_pages() {
+        let mock = MockMeilisearch::start();
+        let client = Client::new(&mock.url, Some(mock_meilisearch::API_KEY));
+        let guard = Guard::new(config::Resilience::default());
+
+        // Every document has the word "de", and all but two have "Art." in their content
+        let query = ParsedQuery::parse("de -content:art");
+        let mut titles = Vec::new();
+        for offset in 0..3 {
+            let results = query_meilisearch(&query, &client, &guard, "entries", offset, 1)
+                .await
+                .unwrap();
+            titles.extend(results.hits.into_iter().map(|hit| hit.result.title));
+        }
+        assert_eq!(
+            titles,
+            [
+                "Ata da reunião do Conselho Universitário de 10/03/2021",
+                "Comunicado sobre o recadastramento de aposentados"
+            ]
+        );
+
+        // The pages are cut from the hits fetched from the first one
+        let searches = mock.searches();
+        assert!(
+            searches
+                .iter()
+                .all(|search| search["offset"] == 0 && search["limit"] == CHECKED_BATCH_SIZE)
+        );
+
+        let query = ParsedQuery::parse("de -content:art -conselho");
+        let results = query_meilisearch(&query, &client, &guard, "entries", 0, 2)
+            .await
+            .unwrap();
+        let titles: Vec<_> = results.hits.into_iter().map(|hit| hit.result.title).collect();
+        assert_eq!(titles, ["Comunicado sobre o recadastramento de aposentados"]);
+    }
+
+    /// Replaces every value of a JSON document by the name of its type, keeping the first element
+    /// of arrays, so that responses can be compared to the shape of a schema.
+    fn shape(value: &serde_json::Value) -> serde_json::Value {
+        use serde_json::Value;
+        match value {
+            Value::Null => "null".into(),
+            Value::Bool(_) => "boolean".into(),
+            Value::Number(number) if number.is_i64() => "integer".into(),
+            Value::Number(_) => "number".into(),
+            Value::String(_) => "string".into(),
+            Value::Array(values) => Value::Array(values.iter().take(1).map(shape).collect()),
+            Value::Object(fields) => {
+                Value::Object(fields.iter().map(|(key, value)| (key.clone(), shape(value))).collect())
+            },
+        }
+    }
+
+    #[actix_rt::test]
+    async fn v1_search_response_has_a_stable_shape() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/search?q=trancamento")
+            .to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(
+            shape(&response),
+            serde_json::json!({
+                "results": [{
+                    "id": "string",
+                    "title": "string",
+                    "date": "integer",
+                    "content": "string",
+                    "link": "string",
+                    "is_normative": "integer",
+                    "collection": "string",
+                }],
+            })
+        );
+
+        let request = test::TestRequest::get().uri("/api/v1/search?q=perspicaz").to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(response, serde_json::json!({ "results": [] }));
+    }
+
+    #[actix_rt::test]
+    async fn v1_related_response_has_a_stable_shape() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let request = test::TestRequest::get().uri("/api/v1/search?q=tecnicos").to_request();
+        let response: serde_json::Value = test::call_and_read_body_json(&app, request).await;
+        let id = response["results"][0]["id"].as_str().unwrap();
+
+        let uri = format!("/api/v1/documents/{id}/related");
+        let response: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert!(!response["results"].as_array().unwrap().is_empty());
+        assert_eq!(
+            shape(&response),
+            serde_json::json!({
+                "results": [{
+                    "id": "string",
+                    "title": "string",
+                    "date": "integer",
+                    "content": "string",
+                    "link": "string",
+                    "is_normative": "integer",
+                }],
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn legacy_search_is_a_deprecated_alias() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let request = test::TestRequest::get().uri("/api/v1/search?q=servidores").to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(response.headers().get("Deprecation").is_none());
+        let current = test::read_body(response).await;
+
+        let request = test::TestRequest::get().uri("/search?q=servidores").to_request();
+        let response = test::call_service(&app, request).await;
+        assert!(response.status().is_success());
+        assert_eq!(response.headers().get("Deprecation").unwrap(), "@1792281600");
+        assert_eq!(
+            response.headers().get("Link").unwrap(),
+            "</api/v1/search>; rel=\"successor-version\""
+        );
+        assert_eq!(test::read_body(response).await, current);
+
+        // Every route deployed before the versioned API is still served
+        let id = "e6abb4cd2d06313a64ed60c32c6442008b3530e753cdaadfb888d5cd72184fff";
+        for request in [
+            test::TestRequest::get().uri("/search/live"),
+            test::TestRequest::get().uri("/export?q=servidores"),
+            test::TestRequest::get().uri(&format!("/documents/{id}/related")),
+            test::TestRequest::post()
+                .uri("/analytics/click")
+                .set_json(serde_json::json!({"event": 1, "document": id, "rank": 1})),
+            test::TestRequest::get().uri("/admin/analytics"),
+        ] {
+            let request = request.to_request();
+            let path = request.path().to_string();
+            let response = test::call_service(&app, request).await;
+            assert_ne!(response.status(), actix_web::http::StatusCode::NOT_FOUND, "{path}");
+            assert_eq!(response.headers().get("Deprecation").unwrap(), "@1792281600", "{path}");
+            assert_eq!(
+                response.headers().get("Link").unwrap().to_str().unwrap(),
+                format!("</api/v1{path}>; rel=\"successor-version\""),
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn results_page_is_paged() {
+        let mock = MockMeilisearch::start();
+        // Every collection is the same index, so that there are more results than fit in a page
+        let collections = ["atas", "boletins", "editais", "portarias", "resolucoes", "sigrh"]
+            .map(|name| (name.to_string(), "entries".to_string()));
+        let app = test_app!(
+            mock,
+            Config {
+                collections: collections.into(),
+                ..Config::default()
+            }
+        );
+        let page = |uri: &str| {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            let app = &app;
+            async move { String::from_utf8(test::call_and_read_body(app, request).await.to_vec()).unwrap() }
+        };
+
+        // Four documents of each of the six collections match
+        let body = page("/buscar?q=cao&collection=*").await;
+        assert!(body.contains("Results 1 to 20 for “cao”."));
+        assert!(body.contains(r#"<ol class="results" start="1">"#));
+        assert!(body.contains(r#"<a rel="next" href="/buscar?q=cao&amp;collection=*&amp;sort=relevance&amp;page=2">"#));
+        assert!(!body.contains(r#"rel="prev""#));
+
+        let body = page("/buscar?q=cao&collection=*&page=2").await;
+        assert!(body.contains("Results 21 to 24 for “cao”."));
+        assert!(body.contains(r#"<ol class="results" start="21">"#));
+        assert_eq!(body.matches("<li>").count(), 4);
+        assert!(body.contains(r#"<a rel="prev" href="/buscar?q=cao&amp;collection=*&amp;sort=relevance&amp;page=1">"#));
+        assert!(!body.contains(r#"rel="next""#));
+
+        // A single collection is paged by Meilisearch, one hit past the page telling whether there
+        // is a next one
+        mock.clear_searches();
+        let body = page("/buscar?q=cao&collection=sigrh&page=3").await;
+        assert!(body.contains("No documents found for “cao”."));
+        assert!(body.contains(r#"rel="prev""#));
+        let searches = mock.searches();
+        assert_eq!(searches.len(), 1);
+        assert_eq!(searches[0]["offset"], 2 * SEARCH_LIMIT);
+        assert_eq!(searches[0]["limit"], SEARCH_LIMIT + 1);
+
+        for uri in [
+            &format!("/buscar?q=cao&page={}", usize::MAX) as &str,
+            &format!("/buscar?q=cao&page={}", usize::MAX / SEARCH_LIMIT + 1),
+        ] {
+            let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), actix_web::http::StatusCode::BAD_REQUEST, "{uri}");
+        }
+        assert_eq!(mock.searches().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn documents_outside_the_default_collection_are_linked_with_it() {
+        const ID: &str = "a22da8b341c2beb2d495f49baf6bb339602e4db4a4b7f330c7b02324d21464ce";
+        let mock = MockMeilisearch::start();
+        let app = test_app!(
+            mock,
+            Config {
+                collections: [("atas", "atas"), ("sigrh", "entries")]
+                    .map(|(name, index)| (name.to_string(), index.to_string()))
+                    .into(),
+                public_base_url: Some("https://normas.unb.br".to_string()),
+                ..Config::default()
+            }
+        );
+        let page = |uri: &str| {
+            let request = test::TestRequest::get().uri(uri).to_request();
+            let app = &app;
+            async move {
+                let response = test::call_service(app, request).await;
+                let status = response.status();
+                (
+                    status,
+                    String::from_utf8(test::read_body(response).await.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        let (_, body) = page("/buscar?q=aposentados&collection=*").await;
+        assert!(body.contains(&format!(
+            r#"<a href="/documents/{ID}/view?collection=atas&amp;q=aposentados">"#
+        )));
+        assert!(body.contains(&format!(r#"<a href="/documents/{ID}/pdf?collection=atas">"#)));
+        assert!(body.contains(&format!(r#"<a href="/documents/{ID}/view?q=aposentados">"#)));
+
+        let (status, body) = page(&format!("/documents/{ID}/view?collection=atas")).await;
+        assert_eq!(status, actix_web::http::StatusCode::OK);
+        assert!(body.contains(&format!(
+            r#"<link rel="canonical" href="https://normas.unb.br/documents/{ID}/view?collection=atas">"#
+        )));
+        assert!(body.contains(&format!(r#"<a href="/documents/{ID}/pdf?collection=atas">"#)));
+        for collection in ["editais", "atas,sigrh"] {
+            let (status, _) = page(&format!("/documents/{ID}/view?collection={collection}")).await;
+            assert_eq!(status, actix_web::http::StatusCode::BAD_REQUEST, "{collection}");
+        }
+
+        // The default collection keeps its sitemaps, the others get their own
+        let (_, body) = page("/sitemap.xml").await;
+        assert!(body.contains("<loc>https://normas.unb.br/sitemaps/0.xml</loc>"));
+        assert!(body.contains("<loc>https://normas.unb.br/sitemaps/atas/0.xml</loc>"));
+        let (_, body) = page("/sitemaps/atas/0.xml").await;
+        assert!(body.contains(&format!(
+            "<loc>https://normas.unb.br/documents/{ID}/view?collection=atas</loc>"
+        )));
+        let (status, _) = page("/sitemaps/sigrh/0.xml").await;
+        assert_eq!(status, actix_web::http::StatusCode::NOT_FOUND);
+
+        // References name the collection of the citing and cited documents
+        let uri = format!("/api/v1/documents/{ID}/references?collection=atas");
+        let (status, _) = page(&uri).await;
+        assert_eq!(status, actix_web::http::StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn results_page_without_a_query_shows_the_form() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        for uri in ["/buscar", "/buscar?q=", "/buscar?q=%20%20&page=2"] {
+            let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert!(response.status().is_success(), "{uri}");
+            let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+            assert!(body.contains(r#"<form class="search" action="/buscar" method="get" role="search">"#));
+            assert!(body.contains(r#"<input type="search" id="q" name="q" value="">"#));
+            assert!(!body.contains("role=\"status\""), "{uri}");
+            assert!(!body.contains("pagination"), "{uri}");
+        }
+
+        // Queries too short to search say so without reaching Meilisearch
+        let request = test::TestRequest::get().uri("/buscar?q=ab").to_request();
+        let body = String::from_utf8(test::call_and_read_body(&app, request).await.to_vec()).unwrap();
+        assert!(body.contains("Type at least 3 characters to search."));
+        assert!(mock.searches().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn cross_origin_requests_follow_the_policy() {
+        use actix_web::http::{StatusCode, header};
+
+        let mock = MockMeilisearch::start();
+        let app = test_app!(
+            mock,
+            Config {
+                settings: config::Settings {
+                    cors: config::Cors {
+                        allowed_origins: vec!["https://portal.unb.br".to_string()],
+                        allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+                        allowed_headers: vec!["Content-Type".to_string(), "X-Search-Event".to_string()],
+                        max_age: 600,
+                    },
+                    ..config::Settings::default()
+                },
+                ..Config::default()
+            }
+        );
+        let preflight = |origin: &str, method: &str| {
+            test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/api/v1/analytics/click")
+                .insert_header((header::ORIGIN, origin))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, method))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, preflight("https://portal.unb.br", "POST")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://portal.unb.br"
+        );
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap(), "GET, POST");
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+            "Content-Type, X-Search-Event"
+        );
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        assert_eq!(headers.get(header::VARY).unwrap(), "Origin");
+
+        for (origin, method) in [
+            ("https://evil.example.com", "POST"),
+            ("https://portal.unb.br", "DELETE"),
+        ] {
+            let response = test::call_service(&app, preflight(origin, method)).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{origin} {method}");
+            assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        }
+
+        // Other requests are answered either way, with the headers letting only allowed origins read
+        // the response
+        let search = |origin: &str| {
+            test::TestRequest::get()
+                .uri("/api/v1/search?q=servidores")
+                .insert_header((header::ORIGIN, origin))
+                .to_request()
+        };
+        let response = test::call_service(&app, search("https://portal.unb.br")).await;
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://portal.unb.br"
+        );
+        let response = test::call_service(&app, search("https://evil.example.com")).await;
+        assert!(response.status().is_success());
+        assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn unchanged_documents_are_not_sent_again() {
+        use actix_web::http::{StatusCode, header};
+
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+        let uri = "/documents/ba31fcd0803ec0051801a25f8be93868b4af2de850fee27ae1e036be2351fab9/view";
+
+        let response = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(
+            response.headers().get(header::CACHE_CONTROL).unwrap(),
+            "public, max-age=3600"
+        );
+
+        let request = test::TestRequest::get()
+            .uri(uri)
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+        assert!(test::read_body(response).await.is_empty());
+
+        // Any other version is sent whole
+        for if_none_match in [r#""0000000000000000""#, r#"W/"0000000000000000""#] {
+            let request = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::IF_NONE_MATCH, if_none_match))
+                .to_request();
+            let response = test::call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers().get(header::ETAG).unwrap(), &etag);
+        }
+        let request = test::TestRequest::get()
+            .uri(uri)
+            .insert_header((header::IF_NONE_MATCH, "*"))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, request).await.status(),
+            StatusCode::NOT_MODIFIED
+        );
+    }
+
+    #[actix_rt::test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored --nocapture`"]
+    async fn bench_search_response_with_large_documents() {
+        // About 2 MB of text per document, as in the longest SIGRH PDFs
+        let content = "Art. 1º Fica aprovado o calendário acadêmico de graduação do segundo semestre. ".repeat(25_000);
+        let hits: Vec<SearchResult<PDFdoc>> = (0..SEARCH_LIMIT)
+            .map(|n| {
+                serde_json::from_value(serde_json::json!({
+                    "id": format!("{n:064x}"),
+                    "title": format!("Resolução CEPE nº {n}/2023"),
+                    "date": 1_672_531_200,
+                    "content": content,
+                    "link": "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1&key=a1",
+                    "is_normative": 1,
+                    "_rankingScore": 0.9,
+                }))
+                .expect("The hit should deserialize.")
+            })
+            .collect();
+        let hits: Arc<[SearchResult<PDFdoc>]> = hits.into();
+        let rounds = 20;
+
+        // Serializing the whole response into a single string, as the search endpoint used to
+        let started = Instant::now();
+        let mut buffered = String::new();
+        for _ in 0..rounds {
+            buffered = serde_json::to_string(&api::v1::SearchResponse {
+                results: hits.iter().map(|hit| (&hit.result).into()).collect(),
+            })
+            .expect("The response should serialize.");
+        }
+        let buffered_time = started.elapsed() / rounds;
+
+        let started = Instant::now();
+        let mut streamed = Vec::new();
+        let mut largest_chunk = 0;
+        for _ in 0..rounds {
+            streamed.clear();
+            let mut body =
+                Box::pin(search_response_body(Arc::clone(&hits), None).expect("The first hit should serialize."));
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.expect("Every hit should serialize.");
+                largest_chunk = largest_chunk.max(chunk.len());
+                streamed.extend_from_slice(&chunk);
+            }
+        }
+        let streamed_time = started.elapsed() / rounds;
+
+        assert_eq!(streamed, buffered.as_bytes());
+        println!(
+            "{SEARCH_LIMIT} documents, {} bytes: buffered in {buffered_time:?} holding {} bytes at once, \
+             streamed in {streamed_time:?} holding at most {largest_chunk} bytes at once",
+            buffered.len(),
+            buffered.len()
+        );
+    }
+
     #[actix_rt::test]
+    #[ignore = "requires a running Meilisearch server and MEILISEARCH_API_KEY"]
     async fn test_query_meilisearch() {
         // Get the API key from the environment, just like in your main function.
         let api_key = env::var("MEILISEARCH_API_KEY").expect("missing MEILISEARCH_API_KEY environment variable.");
@@ -142,7 +1138,15 @@ mod tests {
         let queries = vec!["trancamento", "ProgreÇãO dE carREirA", "troca", "perspicaz"];
 
         for query in queries {
-            let result = query_meilisearch(query, &client).await;
+            let result = query_meilisearch(
+                &ParsedQuery::parse(query),
+                &client,
+                &Guard::new(config::Resilience::default()),
+                "entries",
+                0,
+                SEARCH_LIMIT,
+            )
+            .await;
 
             // Assert that the result is Ok.
             assert!(result.is_ok());
@@ -0,0 +1,448 @@
+//! A stand-in for the Meilisearch server, used by the tests to run the server end to end without a
+//! Meilisearch instance. It implements the endpoints the server calls, with simplified semantics:
+//! a document matches a search if it contains the first words of the query, ignoring case and
+//! accents. As with Meilisearch's default matching strategy, documents containing every word come
+//! first, then those found by dropping the last words one by one. Words negated with `-` exclude
+//! the documents containing them as whole words. Filters are recorded but not applied.
+
+use crate::PDFdoc;
+use crate::text::fold;
+use actix_web::dev::ServerHandle;
+use actix_web::{App, HttpResponse, HttpServer, web};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+/// API key the stand-in expects, as a bearer token.
+pub const API_KEY: &str = "test-key";
+
+/// Document entries as written by Document_Parser to `entries.json`.
+#[derive(Deserialize)]
+struct Data {
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    id: String,
+    title: Option<String>,
+    date: Option<i64>,
+    content: String,
+    link: String,
+    is_normative: i32,
+}
+
+/// Documents of the index and requests received, shared by the workers of the stand-in.
+struct State {
+    documents: RwLock<Vec<PDFdoc>>,
+    searches: Mutex<Vec<Value>>,
+    /// Time every search takes to be answered.
+    search_delay: Mutex<Duration>,
+    tasks: AtomicU32,
+    /// Whether tasks are left enqueued instead of being processed.
+    tasks_held: AtomicBool,
+}
+
+/// A running stand-in, stopped when dropped.
+pub struct MockMeilisearch {
+    pub url: String,
+    state: web::Data<State>,
+    handle: ServerHandle,
+}
+
+/// Loads the fixture entries, in the format Document_Parser writes them.
+fn fixtures() -> Vec<PDFdoc> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/entries.json");
+    let data: Data = serde_json::from_str(&std::fs::read_to_string(path).expect("Could not read the fixtures."))
+        .expect("Invalid fixtures.");
+
+    data.entries
+        .into_iter()
+        .map(|entry| PDFdoc {
+            id: entry.id,
+            title: entry.title.unwrap_or_default(),
+            date: entry.date.unwrap_or_default(),
+            content: entry.content,
+            link: entry.link,
+            is_normative: entry.is_normative,
+            collection: None,
+        })
+        .collect()
+}
+
+/// Answers like Meilisearch does when an API route is called with a missing or wrong key.
+fn check_key(req: &actix_web::HttpRequest) -> Result<(), HttpResponse> {
+    let expected = format!("Bearer {API_KEY}");
+    match req.headers().get("Authorization").and_then(|value| value.to_str().ok()) {
+        Some(value) if value == expected => Ok(()),
+        _ => Err(HttpResponse::Forbidden().json(json!({
+            "message": "The provided API key is invalid.",
+            "code": "invalid_api_key",
+            "type": "auth",
+            "link": "https://docs.meilisearch.com/errors#invalid_api_key",
+        }))),
+    }
+}
+
+/// Returns a task summary, as answered by every route that changes the index.
+fn enqueued(state: &State, index: &str, task_type: &str) -> HttpResponse {
+    HttpResponse::Accepted().json(json!({
+        "taskUid": state.tasks.fetch_add(1, Ordering::Relaxed),
+        "indexUid": index,
+        "status": "enqueued",
+        "type": task_type,
+        "enqueuedAt": "2023-01-01T00:00:00Z",
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchBody {
+    #[serde(default)]
+    q: String,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    attributes_to_search_on: Option<Vec<String>>,
+}
+
+async fn search(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<Value>,
+    state: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = check_key(&req) {
+        return response;
+    }
+    state.searches.lock().unwrap().push(body.0.clone());
+    let delay = *state.search_delay.lock().unwrap();
+    if !delay.is_zero() {
+        actix_rt::time::sleep(delay).await;
+    }
+    let Ok(search) = serde_json::from_value::<SearchBody>(body.0) else {
+        return HttpResponse::BadRequest().finish();
+    };
+
+    let (excluded, words): (Vec<String>, Vec<String>) = fold(&search.q.replace('"', " "))
+        .split_whitespace()
+        .map(str::to_string)
+        .partition(|word| word.starts_with('-'));
+    let searches_title = search
+        .attributes_to_search_on
+        .as_ref()
+        .is_none_or(|attributes| attributes.iter().any(|attribute| attribute == "title"));
+    let searches_content = search
+        .attributes_to_search_on
+        .as_ref()
+        .is_none_or(|attributes| attributes.iter().any(|attribute| attribute == "content"));
+
+    let documents = state.documents.read().unwrap();
+    let texts: Vec<(&PDFdoc, String)> = documents
+        .iter()
+        .filter(|document| {
+            let text = fold(&format!("{} {}", document.title, document.content));
+            let words: Vec<&str> = text.split(|c: char| !c.is_alphanumeric()).collect();
+            !excluded.iter().any(|word| words.contains(&&word[1..]))
+        })
+        .map(|document| {
+            let mut text = String::new();
+            if searches_title {
+                text.push_str(&fold(&document.title));
+            }
+            text.push(' ');
+            if searches_content {
+                text.push_str(&fold(&document.content));
+            }
+            (document, text)
+        })
+        .collect();
+
+    // An empty query matches every document, otherwise at least the first word must be found
+    let mut matching: Vec<&PDFdoc> = Vec::new();
+    for terms in (words.len().min(1)..=words.len()).rev() {
+        for (document, text) in &texts {
+            let matches = words[..terms].iter().all(|word| text.contains(word.as_str()));
+            if matches && !matching.iter().any(|found| found.id == document.id) {
+                matching.push(document);
+            }
+        }
+    }
+
+    let offset = search.offset.unwrap_or(0);
+    let limit = search.limit.unwrap_or(20);
+    let hits: Vec<Value> = matching
+        .iter()
+        .enumerate()
+        .skip(offset)
+        .take(limit)
+        .map(|(rank, document)| {
+            let mut hit = serde_json::to_value(document).unwrap();
+            hit["_rankingScore"] = json!(1.0 / (rank as f64 + 1.0));
+            hit
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({
+        "hits": hits,
+        "query": search.q,
+        "offset": offset,
+        "limit": limit,
+        "estimatedTotalHits": matching.len(),
+        "processingTimeMs": 0,
+        "indexUid": path.into_inner(),
+    }))
+}
+
+async fn get_document(
+    req: actix_web::HttpRequest,
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = check_key(&req) {
+        return response;
+    }
+    let (_, id) = path.into_inner();
+    let documents = state.documents.read().unwrap();
+    match documents.iter().find(|document| document.id == id) {
+        Some(document) => HttpResponse::Ok().json(document),
+        None => HttpResponse::NotFound().json(json!({
+            "message": format!("Document `{id}` not found."),
+            "code": "document_not_found",
+            "type": "invalid_request",
+            "link": "https://docs.meilisearch.com/errors#document_not_found",
+        })),
+    }
+}
+
+#[derive(Deserialize)]
+struct DocumentsParams {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    fields: Option<String>,
+}
+
+async fn get_documents(
+    req: actix_web::HttpRequest,
+    params: web::Query<DocumentsParams>,
+    state: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = check_key(&req) {
+        return response;
+    }
+    let documents = state.documents.read().unwrap();
+    let offset = params.offset.unwrap_or(0);
+    let limit = params.limit.unwrap_or(20);
+    let fields: Option<Vec<&str>> = params.fields.as_deref().map(|fields| fields.split(',').collect());
+
+    let results: Vec<Value> = documents
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|document| {
+            let mut value = serde_json::to_value(document).unwrap();
+            if let (Some(fields), Some(object)) = (&fields, value.as_object_mut()) {
+                object.retain(|key, _| fields.contains(&key.as_str()));
+            }
+            value
+        })
+        .collect();
+
+    HttpResponse::Ok().json(json!({
+        "results": results,
+        "offset": offset,
+        "limit": limit,
+        "total": documents.len(),
+    }))
+}
+
+async fn add_documents(
+    req: actix_web::HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<Vec<PDFdoc>>,
+    state: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = check_key(&req) {
+        return response;
+    }
+    let mut documents = state.documents.write().unwrap();
+    for added in body.into_inner() {
+        match documents.iter_mut().find(|document| document.id == added.id) {
+            Some(document) => *document = added,
+            None => documents.push(added),
+        }
+    }
+    enqueued(&state, &path.into_inner(), "documentAdditionOrUpdate")
+}
+
+async fn delete_document(
+    req: actix_web::HttpRequest,
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = check_key(&req) {
+        return response;
+    }
+    let (index, id) = path.into_inner();
+    state.documents.write().unwrap().retain(|document| document.id != id);
+    enqueued(&state, &index, "documentDeletion")
+}
+
+async fn update_setting(
+    req: actix_web::HttpRequest,
+    path: web::Path<(String, String)>,
+    state: web::Data<State>,
+) -> HttpResponse {
+    if let Err(response) = check_key(&req) {
+        return response;
+    }
+    enqueued(&state, &path.into_inner().0, "settingsUpdate")
+}
+
+/// Every index holds the fixture entries, last updated when the stand-in was written.
+async fn get_index(path: web::Path<String>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "uid": path.into_inner(),
+        "primaryKey": "id",
+        "createdAt": "2023-01-01T00:00:00Z",
+        "updatedAt": "2023-01-01T00:00:00Z",
+    }))
+}
+
+async fn health() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "available" }))
+}
+
+/// Every task is processed as soon as it is enqueued, unless tasks are held.
+async fn get_task(path: web::Path<u32>, state: web::Data<State>) -> HttpResponse {
+    if state.tasks_held.load(Ordering::SeqCst) {
+        return HttpResponse::Ok().json(json!({
+            "uid": path.into_inner(),
+            "indexUid": "entries",
+            "status": "enqueued",
+            "type": "settingsUpdate",
+            "details": null,
+            "canceledBy": null,
+            "error": null,
+            "duration": null,
+            "enqueuedAt": "2023-01-01T00:00:00Z",
+            "startedAt": null,
+            "finishedAt": null,
+        }));
+    }
+    HttpResponse::Ok().json(json!({
+        "uid": path.into_inner(),
+        "indexUid": "entries",
+        "status": "succeeded",
+        "type": "settingsUpdate",
+        "details": null,
+        "canceledBy": null,
+        "error": null,
+        "duration": "PT0.001S",
+        "enqueuedAt": "2023-01-01T00:00:00Z",
+        "startedAt": "2023-01-01T00:00:00Z",
+        "finishedAt": "2023-01-01T00:00:00Z",
+    }))
+}
+
+impl MockMeilisearch {
+    /// Starts a stand-in on a free local port, serving the fixture entries in every index.
+    pub fn start() -> Self {
+        let state = web::Data::new(State {
+            documents: RwLock::new(fixtures()),
+            searches: Mutex::new(Vec::new()),
+            search_delay: Mutex::new(Duration::ZERO),
+            tasks: AtomicU32::new(0),
+            tasks_held: AtomicBool::new(false),
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind the stand-in.");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .route("/indexes/{index}", web::get().to(get_index))
+                .route("/indexes/{index}/search", web::post().to(search))
+                .route("/indexes/{index}/documents", web::get().to(get_documents))
+                .route("/indexes/{index}/documents", web::post().to(add_documents))
+                .route("/indexes/{index}/documents", web::put().to(add_documents))
+                .route("/indexes/{index}/documents/{id}", web::get().to(get_document))
+                .route("/indexes/{index}/documents/{id}", web::delete().to(delete_document))
+                .route("/indexes/{index}/settings/{setting}", web::put().to(update_setting))
+                .route("/indexes/{index}/settings/{setting}", web::patch().to(update_setting))
+                .route("/tasks/{uid}", web::get().to(get_task))
+                .route("/health", web::get().to(health))
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .expect("Could not start the stand-in.")
+        .run();
+
+        let handle = server.handle();
+        actix_rt::spawn(server);
+
+        Self { url, state, handle }
+    }
+
+    /// Makes every search received from now on take the given time to be answered.
+    pub fn delay_searches(&self, delay: Duration) {
+        *self.state.search_delay.lock().unwrap() = delay;
+    }
+
+    /// Leaves every task enqueued while `held`, as a busy Meilisearch would, and processes them
+    /// once released.
+    pub fn hold_tasks(&self, held: bool) {
+        self.state.tasks_held.store(held, Ordering::SeqCst);
+    }
+
+    /// Forgets the search requests received so far.
+    pub fn clear_searches(&self) {
+        self.state.searches.lock().unwrap().clear();
+    }
+
+    /// Returns the bodies of the search requests received, in order.
+    pub fn searches(&self) -> Vec<Value> {
+        self.state.searches.lock().unwrap().clone()
+    }
+}
+
+/// Builds the application on top of a running stand-in, with the default configuration unless one
+/// is given.
+macro_rules! test_app {
+    ($mock:expr) => {
+        $crate::mock_meilisearch::test_app!($mock, $crate::config::Config::default())
+    };
+    ($mock:expr, $config:expr) => {{
+        let config: $crate::config::Config = $config;
+        let client = meilisearch_sdk::client::Client::new(&$mock.url, Some($crate::mock_meilisearch::API_KEY));
+        actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(actix_web::web::Data::new(client))
+                .app_data(actix_web::web::Data::new($crate::resilience::Guard::new(
+                    config.resilience.clone(),
+                )))
+                .app_data(actix_web::web::Data::new($crate::corpus::CorpusCache::default()))
+                .app_data(actix_web::web::Data::new(None::<sqlx::MySqlPool>))
+                .app_data(actix_web::web::Data::new(None::<$crate::analytics::Analytics>))
+                .app_data(actix_web::web::Data::new($crate::config::LiveSettings::new(
+                    config.settings.clone(),
+                )))
+                .app_data(actix_web::web::Data::new($crate::rate_limit::RateLimiter::default()))
+                .app_data(actix_web::web::Data::new(config))
+                .configure(|cfg| $crate::configure(cfg, 0))
+                .wrap_fn($crate::cors::cors),
+        )
+        .await
+    }};
+}
+pub(crate) use test_app;
+
+impl Drop for MockMeilisearch {
+    fn drop(&mut self) {
+        drop(self.handle.stop(false));
+    }
+}
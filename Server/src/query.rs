@@ -0,0 +1,549 @@
+use crate::text::fold;
+use chrono::{Days, NaiveDate};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Searchable attributes that a term can be scoped to with a `title:` or `content:` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Content,
+}
+
+impl Field {
+    /// Returns the name of the attribute in the Meilisearch index.
+    pub const fn attribute(self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Content => "content",
+        }
+    }
+}
+
+/// Order of the results of a search.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Best matches first, as ranked by Meilisearch.
+    #[default]
+    Relevance,
+    Newest,
+    Oldest,
+}
+
+impl SortOrder {
+    /// Returns the Meilisearch sort expression, or `None` to keep the ranking by relevance.
+    pub const fn expression(self) -> Option<&'static str> {
+        match self {
+            Self::Relevance => None,
+            Self::Newest => Some("date:desc"),
+            Self::Oldest => Some("date:asc"),
+        }
+    }
+}
+
+/// A search query translated from the advanced query syntax into what Meilisearch understands.
+///
+/// The syntax accepts, separated by whitespace:
+///
+/// - plain words and `"quoted phrases"`, which are sent to Meilisearch as they are;
+/// - `-word` and `-"quoted phrase"`, which exclude documents containing them as whole words;
+/// - `title:word` and `content:word` (or `titulo:`/`conteudo:`), which only match whole words in
+///   that field;
+/// - `ano:2021`, which keeps only documents dated in that year;
+/// - `tipo:normativa`, `tipo:deliberativa` and `tipo:outros`, which filter by category.
+///
+/// Shortcuts can also be negated, as in `-tipo:deliberativa`. Anything that does not parse as a
+/// shortcut, such as `ano:abc` or `http://`, is kept as a plain term.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ParsedQuery {
+    /// The query string sent to Meilisearch, with phrases kept between double quotes. Excluded
+    /// terms that are not scoped to a field come last, negated with Meilisearch's own `-`.
+    pub text: String,
+    /// Attributes to search on. Empty means every searchable attribute.
+    pub attributes: Vec<&'static str>,
+    /// Meilisearch filter expressions, all of which must hold.
+    pub filters: Vec<String>,
+    /// Order of the results. Not part of the syntax, set from the request parameters.
+    pub sort: SortOrder,
+    /// Folded terms that a hit must not contain, optionally only in one field, and that Meilisearch
+    /// cannot exclude itself.
+    pub excluded: Vec<(Option<Field>, String)>,
+    /// Folded terms that a hit must contain in a specific field.
+    pub required: Vec<(Field, String)>,
+    /// Folded terms and phrases the query looks for, used to highlight them in documents.
+    pub terms: Vec<String>,
+}
+
+/// A single whitespace separated piece of the query, before interpretation.
+struct Token<'a> {
+    negated: bool,
+    prefix: Option<&'a str>,
+    value: String,
+    phrase: bool,
+}
+
+impl ParsedQuery {
+    /// Parses a query written in the advanced query syntax. Parsing never fails: malformed input,
+    /// such as an unterminated phrase, is interpreted as closely as possible.
+    pub fn parse(input: &str) -> Self {
+        let mut parsed = Self::default();
+        let mut positive_fields: Vec<Option<Field>> = Vec::new();
+        let mut negated = Vec::new();
+
+        for token in tokenize(input) {
+            let field = match token.prefix.map(fold).as_deref() {
+                Some("title" | "titulo") => Some(Field::Title),
+                Some("content" | "conteudo") => Some(Field::Content),
+                Some("ano") => {
+                    if let Some(filter) = year_filter(&token.value, token.negated) {
+                        parsed.filters.push(filter);
+                        continue;
+                    }
+                    None
+                },
+                Some("tipo") => {
+                    if let Some(category) = category(&token.value) {
+                        let operator = if token.negated { "!=" } else { "=" };
+                        parsed.filters.push(format!("is_normative {operator} {category}"));
+                        continue;
+                    }
+                    None
+                },
+                _ => None,
+            };
+
+            // Unknown prefixes are not shortcuts, so they stay part of the term.
+            let value = match (token.prefix, field) {
+                (Some(prefix), None) => format!("{prefix}:{}", token.value),
+                _ => token.value,
+            };
+
+            if token.negated {
+                match field {
+                    Some(_) => parsed.exclude(field, &value),
+                    None => negated.push((value, token.phrase)),
+                }
+                continue;
+            }
+
+            if let Some(field) = field {
+                if has_words(&value) {
+                    parsed.required.push((field, fold(&value)));
+                }
+            }
+            parsed.terms.push(fold(&value));
+            positive_fields.push(field);
+
+            push_term(&mut parsed.text, "", &value, token.phrase);
+        }
+
+        // Meilisearch can only restrict the whole query to some attributes, so this is done only when
+        // every term is scoped to the same field. Mixed queries rely on the `required` check instead.
+        if let Some(Some(first)) = positive_fields.first() {
+            if positive_fields.iter().all(|field| *field == Some(*first)) {
+                parsed.attributes.push(first.attribute());
+            }
+        }
+
+        // A negated term in the text would then only be excluded from the restricted attributes, so
+        // it is checked on the hits instead.
+        for (value, phrase) in negated {
+            if parsed.attributes.is_empty() {
+                push_term(&mut parsed.text, "-", &value, phrase);
+            } else {
+                parsed.exclude(None, &value);
+            }
+        }
+
+        parsed
+    }
+
+    /// Adds a term to exclude on the hits. Terms without any word could never be found, so they are
+    /// left out.
+    fn exclude(&mut self, field: Option<Field>, value: &str) {
+        if has_words(value) {
+            self.excluded.push((field, fold(value)));
+        }
+    }
+
+    /// Returns true when there is nothing to send to Meilisearch, i.e. no terms and no filters.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty() && self.filters.is_empty()
+    }
+
+    /// Returns true when some hits of the Meilisearch search may still be rejected by
+    /// [`accepts`](Self::accepts).
+    pub fn checks_hits(&self) -> bool {
+        !self.excluded.is_empty() || !self.required.is_empty()
+    }
+
+    /// Checks the parts of the query that Meilisearch cannot express: excluded terms and terms
+    /// scoped to a field. Returns true if a document with the given title and content should be
+    /// kept in the results. Terms match whole words, and phrases the same words in a row.
+    pub fn accepts(&self, title: &str, content: &str) -> bool {
+        if !self.checks_hits() {
+            return true;
+        }
+
+        let title = fold(title);
+        let content = fold(content);
+        let title = words(&title);
+        let content = words(&content);
+        let contains = |field: Option<Field>, term: &str| match field {
+            Some(Field::Title) => contains_words(&title, term),
+            Some(Field::Content) => contains_words(&content, term),
+            None => contains_words(&title, term) || contains_words(&content, term),
+        };
+
+        self.required.iter().all(|(field, term)| contains(Some(*field), term))
+            && !self.excluded.iter().any(|(field, term)| contains(*field, term))
+    }
+}
+
+/// Appends a term to the text sent to Meilisearch, after `operator`.
+fn push_term(text: &mut String, operator: &str, value: &str, phrase: bool) {
+    if !text.is_empty() {
+        text.push(' ');
+    }
+    text.push_str(operator);
+    if phrase {
+        text.push('"');
+        text.push_str(value);
+        text.push('"');
+    } else {
+        text.push_str(value);
+    }
+}
+
+/// Splits folded text into its words, the runs of letters and digits.
+fn words(text: &str) -> Vec<&str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Returns true if the text has at least one word.
+fn has_words(text: &str) -> bool {
+    text.chars().any(char::is_alphanumeric)
+}
+
+/// Returns true if the words of a folded term appear in a row in `words`.
+fn contains_words(words: &[&str], term: &str) -> bool {
+    let term = self::words(term);
+    !term.is_empty() && words.windows(term.len()).any(|window| window == term.as_slice())
+}
+
+/// Splits the query into tokens, handling the `-` negation, `prefix:` scoping and double quoted
+/// phrases. Double quotes inside plain words are dropped so that the text sent to Meilisearch never
+/// contains an unbalanced phrase.
+fn tokenize(input: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        let negated = rest.starts_with('-');
+        if negated {
+            rest = &rest[1..];
+        }
+
+        let mut prefix = None;
+        if let Some(colon) = rest.find(':') {
+            let candidate = &rest[..colon];
+            if !candidate.is_empty() && candidate.chars().all(char::is_alphabetic) {
+                prefix = Some(candidate);
+                rest = &rest[colon + 1..];
+            }
+        }
+
+        let (value, phrase, remaining) = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let remaining = quoted.get(end + 1..).unwrap_or_default();
+            (
+                quoted[..end].split_whitespace().collect::<Vec<_>>().join(" "),
+                true,
+                remaining,
+            )
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (rest[..end].replace('"', ""), false, &rest[end..])
+        };
+
+        if !value.is_empty() {
+            tokens.push(Token {
+                negated,
+                prefix,
+                value,
+                phrase,
+            });
+        }
+
+        rest = remaining.trim_start();
+    }
+
+    tokens
+}
+
+/// Builds the filter for an `ano:` shortcut, covering every document dated inside that year.
+fn year_filter(value: &str, negated: bool) -> Option<String> {
+    let year: i32 = value.parse().ok().filter(|year| (1900..=2100).contains(year))?;
+    let start = NaiveDate::from_ymd_opt(year, 1, 1)?.and_hms_opt(0, 0, 0)?.timestamp();
+    let end = NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+        .and_hms_opt(0, 0, 0)?
+        .timestamp();
+
+    Some(if negated {
+        format!("date < {start} OR date >= {end}")
+    } else {
+        format!("date >= {start} AND date < {end}")
+    })
+}
+
+/// Builds the filter keeping only documents dated between two days, both included. Either bound
+/// can be left out. Returns `None` if there is no bound.
+pub fn date_range_filter(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Option<String> {
+    let start = from
+        .and_then(|from| from.and_hms_opt(0, 0, 0))
+        .map(|start| start.timestamp());
+    let end = to
+        .and_then(|to| to.checked_add_days(Days::new(1)))
+        .and_then(|end| end.and_hms_opt(0, 0, 0))
+        .map(|end| end.timestamp());
+
+    match (start, end) {
+        (Some(start), Some(end)) => Some(format!("date >= {start} AND date < {end}")),
+        (Some(start), None) => Some(format!("date >= {start}")),
+        (None, Some(end)) => Some(format!("date < {end}")),
+        (None, None) => None,
+    }
+}
+
+/// Maps the value of a `tipo:` shortcut to the `is_normative` category used by Document_Parser:
+/// 1 for normative, 2 for deliberative and 3 for unspecified documents.
+fn category(value: &str) -> Option<i32> {
+    let value = fold(value);
+    if value.starts_with("normativ") {
+        Some(1)
+    } else if value.starts_with("deliberativ") {
+        Some(2)
+    } else if ["outr", "nao", "indefinid"]
+        .iter()
+        .any(|prefix| value.starts_with(prefix))
+    {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// Normalizes a raw search query before it is parsed. Control characters are dropped, runs of
+/// whitespace are collapsed into a single space, the text is composed (NFC) so that an accented
+/// letter counts as a single character however it was typed, and it is cut to at most `max_length`
+/// characters. The cut falls between grapheme clusters, so a letter is never split from its
+/// accents.
+pub fn normalize(query: &str, max_length: usize) -> String {
+    let mut cleaned = String::with_capacity(query.len());
+    let mut pending_space = false;
+    for c in query.chars() {
+        if c.is_whitespace() {
+            pending_space = !cleaned.is_empty();
+            continue;
+        }
+        if c.is_control() {
+            continue;
+        }
+        if pending_space {
+            cleaned.push(' ');
+            pending_space = false;
+        }
+        cleaned.push(c);
+    }
+
+    let mut normalized: String = cleaned.nfc().collect();
+    let mut length = 0;
+    let end = normalized.grapheme_indices(true).find_map(|(start, grapheme)| {
+        length += grapheme.chars().count();
+        (length > max_length).then_some(start)
+    });
+    if let Some(end) = end {
+        normalized.truncate(normalized[..end].trim_end().len());
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use unicode_normalization::is_nfc;
+
+    #[test]
+    fn test_parse_example_query() {
+        let parsed = ParsedQuery::parse(r#""regime de dedicação exclusiva" -revogada title:CONSEPE"#);
+
+        assert_eq!(parsed.text, r#""regime de dedicação exclusiva" CONSEPE -revogada"#);
+        assert!(parsed.excluded.is_empty());
+        assert_eq!(parsed.required, vec![(Field::Title, "consepe".to_string())]);
+        assert!(parsed.attributes.is_empty());
+        assert!(parsed.filters.is_empty());
+
+        assert!(parsed.accepts("RESOLUÇÃO DO CONSEPE Nº 1", "Regime de dedicação\nexclusiva"));
+        assert!(!parsed.accepts("RESOLUÇÃO DO CAD Nº 1", "Regime de dedicação exclusiva"));
+        assert!(parsed.accepts("RESOLUÇÃO CONSEPE/CAD Nº 1", "Regime de dedicação exclusiva"));
+        assert!(!parsed.accepts("RESOLUÇÃO DO SUBCONSEPE Nº 1", "Regime de dedicação exclusiva"));
+    }
+
+    #[test]
+    fn test_scoped_terms_match_whole_words() {
+        let parsed = ParsedQuery::parse("title:CAD");
+        assert!(parsed.accepts("Resolução do CAD nº 8/2023", ""));
+        assert!(parsed.accepts("Resolução CAD/UnB", ""));
+        assert!(!parsed.accepts("Comunicado sobre o recadastramento", ""));
+        assert!(!parsed.accepts("Cadastro de aposentados", ""));
+
+        let parsed = ParsedQuery::parse("progressão -content:ata -titulo:\"conselho universitario\"");
+        assert_eq!(parsed.text, "progressão");
+        assert!(parsed.accepts("Resolução", "Data de matrícula"));
+        assert!(parsed.accepts("Conselho de Ensino", "Universitário"));
+        assert!(!parsed.accepts("Resolução", "Conforme a ATA da reunião"));
+        assert!(!parsed.accepts("Reunião do Conselho Universitário", ""));
+
+        // Meilisearch could only exclude the term from the titles here
+        let parsed = ParsedQuery::parse("title:carreira -revogada");
+        assert_eq!(parsed.text, "carreira");
+        assert_eq!(parsed.attributes, vec!["title"]);
+        assert_eq!(parsed.excluded, vec![(None, "revogada".to_string())]);
+        assert!(!parsed.accepts("Carreira docente", "Resolução revogada."));
+        assert!(parsed.accepts("Carreira docente", "Resolução não revogável"));
+
+        // Nothing is left to search for, and nothing to check for terms without words
+        assert!(ParsedQuery::parse("-revogada").is_empty());
+        assert!(!ParsedQuery::parse("ano:2021 -revogada").is_empty());
+        assert!(!ParsedQuery::parse("title:!! -content:--").checks_hits());
+    }
+
+    #[test]
+    fn test_parse_shortcuts() {
+        let parsed = ParsedQuery::parse("ano:2021 tipo:Normativa -tipo:deliberativa ano:abc");
+
+        assert_eq!(parsed.text, "ano:abc");
+        assert_eq!(
+            parsed.filters,
+            vec![
+                "date >= 1609459200 AND date < 1640995200".to_string(),
+                "is_normative = 1".to_string(),
+                "is_normative != 2".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_cleans_and_composes() {
+        assert_eq!(
+            normalize("  Progressa\u{303}o\u{0}  de\t\ncarreira ", 200),
+            "Progressão de carreira"
+        );
+        assert_eq!(normalize("\u{7}\u{1b}", 200), "");
+
+        // A multi-byte character is kept whole, and an accent is not split from its letter
+        let query = format!("{}ção", "a".repeat(199));
+        assert_eq!(normalize(&query, 200), format!("{}ç", "a".repeat(199)));
+        let query = format!("{}q\u{323}\u{307}", "a".repeat(199));
+        assert_eq!(normalize(&query, 200), "a".repeat(199));
+        assert_eq!(normalize("férias de servidores", 10), "férias de");
+    }
+
+    proptest! {
+        #[test]
+        fn test_normalize_never_panics_and_respects_the_length(input in any::<String>(), max_length in 0..300usize) {
+            let normalized = normalize(&input, max_length);
+            prop_assert!(normalized.chars().count() <= max_length);
+            prop_assert!(is_nfc(&normalized));
+            prop_assert!(!normalized.chars().any(char::is_control));
+            prop_assert_eq!(normalized.trim(), &normalized);
+            prop_assert!(!normalized.contains("  "));
+            prop_assert!(normalized.chars().all(|c| c == ' ' || !c.is_whitespace()));
+        }
+
+        #[test]
+        fn test_normalize_cuts_between_graphemes(input in any::<String>(), max_length in 0..300usize) {
+            let whole = normalize(&input, usize::MAX);
+            let cut = normalize(&input, max_length);
+            prop_assert!(whole.starts_with(&cut));
+            let boundaries: Vec<usize> = whole.grapheme_indices(true).map(|(start, _)| start).collect();
+            prop_assert!(cut.len() == whole.len() || boundaries.contains(&cut.len()));
+            prop_assert_eq!(normalize(&cut, max_length), cut);
+        }
+
+        #[test]
+        fn test_normalize_keeps_clean_queries(words in prop::collection::vec("[a-zà-úA-ZÀ-Ú0-9]{1,12}", 1..8)) {
+            let query = words.join(" ");
+            prop_assert_eq!(normalize(&query, usize::MAX), query);
+        }
+
+        #[test]
+        fn test_parse_never_panics_and_balances_quotes(input in ".*") {
+            let parsed = ParsedQuery::parse(&input);
+            prop_assert_eq!(parsed.text.matches('"').count() % 2, 0);
+        }
+
+        #[test]
+        fn test_plain_words_are_kept(words in prop::collection::vec("[a-zà-ú]{1,12}", 1..8)) {
+            let input = words.join(" ");
+            let parsed = ParsedQuery::parse(&input);
+            prop_assert_eq!(parsed.text, input);
+            prop_assert!(parsed.filters.is_empty());
+            prop_assert!(parsed.attributes.is_empty());
+            prop_assert!(parsed.excluded.is_empty());
+        }
+
+        #[test]
+        fn test_negated_words_are_excluded(kept in "[a-z]{3,12}", excluded in "[a-z]{3,12}") {
+            let parsed = ParsedQuery::parse(&format!("{kept} -{excluded}"));
+            prop_assert_eq!(&parsed.text, &format!("{kept} -{excluded}"));
+            prop_assert!(!parsed.checks_hits());
+
+            let parsed = ParsedQuery::parse(&format!("{kept} -content:{excluded}"));
+            prop_assert_eq!(&parsed.text, &kept);
+            prop_assert_eq!(&parsed.excluded, &vec![(Some(Field::Content), excluded.clone())]);
+            prop_assert_eq!(parsed.accepts("", &kept), kept != excluded);
+            prop_assert_eq!(parsed.accepts(&excluded, &kept), kept != excluded);
+            let sentence = format!("{kept}, {excluded}.");
+            prop_assert!(!parsed.accepts("", &sentence));
+            let longer_words = format!("{kept}{excluded} a{excluded}");
+            prop_assert!(parsed.accepts("", &longer_words));
+        }
+
+        #[test]
+        fn test_phrases_are_quoted(words in prop::collection::vec("[a-z]{1,12}", 1..6)) {
+            let phrase = words.join(" ");
+            let parsed = ParsedQuery::parse(&format!("  \"{phrase}\"  "));
+            prop_assert_eq!(parsed.text, format!("\"{phrase}\""));
+        }
+
+        #[test]
+        fn test_scoped_terms_restrict_attributes(title in "[a-z]{1,12}", content in "[a-z]{1,12}") {
+            let parsed = ParsedQuery::parse(&format!("title:{title} titulo:\"{content}\""));
+            prop_assert_eq!(parsed.attributes, vec!["title"]);
+
+            let parsed = ParsedQuery::parse(&format!("title:{title} content:{content}"));
+            prop_assert!(parsed.attributes.is_empty());
+            prop_assert!(parsed.accepts(&title, &content));
+            prop_assert_eq!(parsed.accepts(&content, &title), content == title);
+            let (longer_title, longer_content) = (format!("x{title}"), format!("{content}x"));
+            prop_assert!(!parsed.accepts(&longer_title, &longer_content));
+        }
+
+        #[test]
+        fn test_year_filter_bounds(year in 1900..=2100i32) {
+            let parsed = ParsedQuery::parse(&format!("ano:{year}"));
+            prop_assert!(parsed.text.is_empty());
+            prop_assert!(!parsed.is_empty());
+
+            let start = NaiveDate::from_ymd_opt(year, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().timestamp();
+            let start = start.to_string();
+            prop_assert_eq!(parsed.filters[0].split(' ').nth(2), Some(start.as_str()));
+        }
+    }
+}
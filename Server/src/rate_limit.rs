@@ -0,0 +1,271 @@
+use crate::config::{Config, LiveSettings, RateLimit};
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use futures_util::future::{self, Either, TryFutureExt};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of clients tracked above which those whose window is over are forgotten.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Counts the requests made by each client in fixed time windows.
+#[derive(Default)]
+pub struct RateLimiter {
+    windows: Mutex<Windows>,
+}
+
+/// The windows of the clients, and when those that were over were last forgotten.
+#[derive(Default)]
+struct Windows {
+    /// Start of the current window and number of requests made in it, by client address.
+    by_client: HashMap<String, (Instant, u32)>,
+    last_prune: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Records a request from the given client and tells whether it is within the limit. The limit
+    /// is passed on every call rather than stored, so that reloaded settings apply immediately.
+    pub fn check(&self, client: &str, limit: RateLimit) -> bool {
+        self.check_at(client, limit, Instant::now())
+    }
+
+    /// Records a request from the given client made at `now`, see [`RateLimiter::check`].
+    fn check_at(&self, client: &str, limit: RateLimit, now: Instant) -> bool {
+        if limit.requests == 0 {
+            return true;
+        }
+
+        let window = Duration::from_secs(limit.window);
+        let mut windows = self.windows.lock().expect("Rate limiter lock poisoned.");
+
+        // Forget the clients whose window is over, so that the map does not grow forever. Going
+        // through the map takes a while, so it is done at most once per window
+        let pruned_recently = windows
+            .last_prune
+            .is_some_and(|last_prune| now.duration_since(last_prune) < window);
+        if windows.by_client.len() > PRUNE_THRESHOLD && !pruned_recently {
+            windows
+                .by_client
+                .retain(|_, (start, _)| now.duration_since(*start) < window);
+            windows.last_prune = Some(now);
+        }
+
+        let (start, count) = windows.by_client.entry(client.to_string()).or_insert((now, 0));
+        if now.duration_since(*start) >= window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+
+        *count <= limit.requests
+    }
+}
+
+/// Returns the address of the client that sent a request. The address of the connection is used,
+/// unless it is one of the trusted proxies, in which case the X-Forwarded-For header is followed
+/// back from the last address a trusted proxy added. Clients can write anything in that header, so
+/// it is never believed for connections that do not come from a trusted proxy.
+pub fn client_address(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> String {
+    let Some(mut address) = req.peer_addr().map(|peer| peer.ip()) else {
+        return String::new();
+    };
+
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    for hop in forwarded.iter().rev() {
+        if !trusted_proxies.contains(&address) {
+            break;
+        }
+        match hop
+            .parse::<IpAddr>()
+            .or_else(|_| hop.parse::<SocketAddr>().map(|hop| hop.ip()))
+        {
+            Ok(hop) => address = hop,
+            Err(_) => break,
+        }
+    }
+
+    address.to_string()
+}
+
+/// Applies the rate limit of the current settings to each client. Only wraps the API and the search
+/// page, so that static files, sitemaps and the probes of load balancers and monitoring are left
+/// out.
+pub fn limit<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<EitherBody<B>>, Error>> + 'static
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    let allowed = match (
+        req.app_data::<web::Data<RateLimiter>>(),
+        req.app_data::<web::Data<LiveSettings>>(),
+    ) {
+        (Some(limiter), Some(settings)) => {
+            let trusted_proxies = req
+                .app_data::<web::Data<Config>>()
+                .map(|config| config.trusted_proxies.as_slice())
+                .unwrap_or_default();
+            limiter.check(
+                &client_address(req.request(), trusted_proxies),
+                settings.get().rate_limit,
+            )
+        },
+        _ => true,
+    };
+
+    if allowed {
+        Either::Left(srv.call(req).map_ok(ServiceResponse::map_into_left_body))
+    } else {
+        let response = HttpResponse::TooManyRequests().body("Too many requests");
+        Either::Right(future::ok(req.into_response(response).map_into_right_body()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service};
+
+    const LIMIT: RateLimit = RateLimit {
+        requests: 2,
+        window: 60,
+    };
+
+    #[test]
+    fn test_requests_are_counted_per_client() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        assert!(limiter.check_at("10.0.0.1", LIMIT, now));
+        assert!(limiter.check_at("10.0.0.1", LIMIT, now));
+        assert!(!limiter.check_at("10.0.0.1", LIMIT, now));
+        assert!(!limiter.check_at("10.0.0.1", LIMIT, now + Duration::from_secs(59)));
+
+        // Other clients have their own count
+        assert!(limiter.check_at("10.0.0.2", LIMIT, now));
+    }
+
+    #[test]
+    fn test_window_resets_the_count() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            limiter.check_at("10.0.0.1", LIMIT, now);
+        }
+        let later = now + Duration::from_secs(60);
+        assert!(limiter.check_at("10.0.0.1", LIMIT, later));
+        assert!(limiter.check_at("10.0.0.1", LIMIT, later));
+        assert!(!limiter.check_at("10.0.0.1", LIMIT, later));
+    }
+
+    #[test]
+    fn test_zero_requests_disables_the_limit() {
+        let limiter = RateLimiter::default();
+        let unlimited = RateLimit { requests: 0, ..LIMIT };
+        assert!((0..100).all(|_| limiter.check("10.0.0.1", unlimited)));
+        assert!(limiter.windows.lock().unwrap().by_client.is_empty());
+    }
+
+    #[test]
+    fn test_expired_windows_are_pruned_once_per_window() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        let tracked = || limiter.windows.lock().unwrap().by_client.len();
+
+        for client in 0..=PRUNE_THRESHOLD {
+            limiter.check_at(&client.to_string(), LIMIT, now);
+        }
+        assert_eq!(tracked(), PRUNE_THRESHOLD + 1);
+
+        // Once their window is over, the clients are forgotten
+        let later = now + Duration::from_secs(60);
+        limiter.check_at("new", LIMIT, later);
+        assert_eq!(tracked(), 1);
+
+        // Until the next window, the map is not gone through again
+        for client in 0..=PRUNE_THRESHOLD {
+            limiter.check_at(&client.to_string(), LIMIT, later);
+        }
+        limiter.check_at("other", LIMIT, later + Duration::from_secs(59));
+        assert_eq!(tracked(), PRUNE_THRESHOLD + 3);
+        limiter.check_at("other", LIMIT, later + Duration::from_secs(120));
+        assert_eq!(tracked(), 1);
+    }
+
+    #[test]
+    fn test_forwarded_addresses_are_only_believed_from_trusted_proxies() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let request = |peer: &str, forwarded: Option<&str>| {
+            let mut request = TestRequest::default().peer_addr(peer.parse().unwrap());
+            if let Some(forwarded) = forwarded {
+                request = request.insert_header(("X-Forwarded-For", forwarded));
+            }
+            request.to_http_request()
+        };
+
+        // Without trusted proxies, or from another peer, the header is ignored
+        let spoofed = request("203.0.113.7:4000", Some("198.51.100.1"));
+        assert_eq!(client_address(&spoofed, &[]), "203.0.113.7");
+        assert_eq!(client_address(&spoofed, &[proxy]), "203.0.113.7");
+
+        // The client can prepend anything, only the address the proxy appended counts
+        let forwarded = request("10.0.0.1:4000", Some("198.51.100.1, 203.0.113.7"));
+        assert_eq!(client_address(&forwarded, &[proxy]), "203.0.113.7");
+        assert_eq!(client_address(&forwarded, &[]), "10.0.0.1");
+
+        // Chains of trusted proxies are followed back
+        let second: IpAddr = "10.0.0.2".parse().unwrap();
+        let chained = request("10.0.0.1:4000", Some("203.0.113.7:5000, 10.0.0.2"));
+        assert_eq!(client_address(&chained, &[proxy, second]), "203.0.113.7");
+
+        let missing = request("10.0.0.1:4000", None);
+        assert_eq!(client_address(&missing, &[proxy]), "10.0.0.1");
+        let invalid = request("10.0.0.1:4000", Some("unknown"));
+        assert_eq!(client_address(&invalid, &[proxy]), "10.0.0.1");
+    }
+
+    #[actix_rt::test]
+    async fn only_the_api_and_the_search_page_are_limited() {
+        let mock = MockMeilisearch::start();
+        let mut config = Config::default();
+        config.settings.rate_limit = RateLimit {
+            requests: 1,
+            window: 60,
+        };
+        let app = test_app!(mock, config);
+        let status = |uri: &'static str| {
+            let app = &app;
+            async move {
+                call_service(app, TestRequest::get().uri(uri).to_request())
+                    .await
+                    .status()
+            }
+        };
+
+        for _ in 0..3 {
+            for uri in ["/robots.txt", "/sitemap.xml", "/ready", "/metrics"] {
+                assert_ne!(status(uri).await, StatusCode::TOO_MANY_REQUESTS, "{uri}");
+            }
+        }
+        assert_eq!(status("/api/v1/stats").await, StatusCode::OK);
+        for uri in ["/api/v1/stats", "/buscar?q=servidores", "/search?q=servidores"] {
+            assert_eq!(status(uri).await, StatusCode::TOO_MANY_REQUESTS, "{uri}");
+        }
+    }
+}
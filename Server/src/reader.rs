@@ -0,0 +1,367 @@
+use crate::PDFdoc;
+use crate::html::{escape, page};
+use crate::i18n::Locale;
+use crate::text::{find_matches, fold, snippet};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+/// Number of characters of the content used as the description of a document page.
+const DESCRIPTION_LENGTH: usize = 160;
+
+/// A paragraph of a document, with the lines pdftotext split it into joined back together.
+struct Paragraph {
+    text: String,
+    /// Page the paragraph starts on, counting from 1.
+    page: usize,
+    /// Id of the article, chapter or section the paragraph starts, if any.
+    anchor: Option<String>,
+    /// Text of the marker that produced the anchor, e.g. “Art. 5” or “CAPÍTULO II”.
+    label: Option<String>,
+    /// Whether the paragraph is a chapter, section, title or annex heading.
+    heading: bool,
+}
+
+/// Matches the start of an article (“Art. 5º”) or of a chapter, section, title or annex heading
+/// (“CAPÍTULO II”).
+fn structure_marker() -> &'static Regex {
+    static MARKER: OnceLock<Regex> = OnceLock::new();
+    MARKER.get_or_init(|| {
+        Regex::new(concat!(
+            r"(?i)^(?:(?P<article>art(?:igo)?\.?)\s*(?P<number>\d+)",
+            r"|(?P<heading>cap[ií]tulo|se[cç][aã]o|t[ií]tulo|anexo)\s+(?P<numeral>[ivxlcdm]+|\d+)\b)",
+        ))
+        .expect("Invalid Regular Expression for structure markers.")
+    })
+}
+
+/// Matches the start of a paragraph, item or subitem inside an article (“§ 1º”, “Parágrafo único”,
+/// “IV -”, “a)”).
+fn item_marker() -> &'static Regex {
+    static MARKER: OnceLock<Regex> = OnceLock::new();
+    MARKER.get_or_init(|| {
+        Regex::new(r"(?i)^(?:§|par[aá]grafo [uú]nico|[ivxlcdm]+\s*[-–—]\s|[a-z]\)\s)")
+            .expect("Invalid Regular Expression for item markers.")
+    })
+}
+
+/// Rebuilds the paragraphs of a text extracted by pdftotext, which breaks lines at the width of
+/// the page and separates pages with form feeds. Lines are joined until a blank line or the start
+/// of an article, heading or item, and words hyphenated across lines are put back together.
+fn reflow(content: &str) -> Vec<Paragraph> {
+    let mut paragraphs: Vec<Paragraph> = Vec::new();
+    let mut anchor_counts: HashMap<String, usize> = HashMap::new();
+
+    let mut push = |text: &mut String, page: usize| {
+        if text.is_empty() {
+            return;
+        }
+
+        let captures = structure_marker().captures(text);
+        let (anchor, label, heading) = captures.map_or((None, None, false), |captures| {
+            let label = captures[0].trim_end_matches('.').to_string();
+            let (kind, number, heading) = match (captures.name("article"), captures.name("heading")) {
+                (Some(_), _) => ("art".to_string(), &captures["number"], false),
+                (_, Some(heading)) => (fold(heading.as_str()), &captures["numeral"], true),
+                _ => unreachable!("The structure marker always matches an article or a heading."),
+            };
+
+            // Annexes often restart the numbering of articles, so repeated anchors get a suffix
+            let anchor = format!("{kind}-{}", number.to_lowercase());
+            let count = anchor_counts.entry(anchor.clone()).or_default();
+            *count += 1;
+            let anchor = if *count == 1 {
+                anchor
+            } else {
+                format!("{anchor}-{count}")
+            };
+
+            (Some(anchor), Some(label), heading)
+        });
+
+        paragraphs.push(Paragraph {
+            text: std::mem::take(text),
+            page,
+            anchor,
+            label,
+            heading,
+        });
+    };
+
+    for (page_index, page_text) in content.split('\u{c}').enumerate() {
+        let mut current = String::new();
+
+        for line in page_text.lines().map(str::trim) {
+            let starts_block = line.is_empty() || structure_marker().is_match(line) || item_marker().is_match(line);
+            if starts_block {
+                push(&mut current, page_index + 1);
+            }
+            if line.is_empty() {
+                continue;
+            }
+
+            if current.ends_with('-') && line.starts_with(char::is_lowercase) {
+                current.pop();
+            } else if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(line);
+        }
+
+        push(&mut current, page_index + 1);
+    }
+
+    paragraphs
+}
+
+/// Appends the escaped text to the page, wrapping each match in a highlighted link to the next
+/// one, so that following the highlighted terms walks through every match and then back to the
+/// first. `match_number` is the number of matches already written.
+fn write_highlighted(
+    html: &mut String,
+    text: &str,
+    terms: &[String],
+    match_number: &mut usize,
+    total: usize,
+    locale: Locale,
+) {
+    let mut written = 0;
+    for range in find_matches(text, terms) {
+        *match_number += 1;
+        let next = if *match_number == total { 1 } else { *match_number + 1 };
+        let _ = write!(
+            html,
+            r##"{}<a class="match" id="match-{match_number}" href="#match-{next}" title="{}"><mark>{}</mark></a>"##,
+            escape(&text[written..range.start]),
+            locale.text("Next match", "Próxima ocorrência"),
+            escape(&text[range.clone()]),
+        );
+        written = range.end;
+    }
+    html.push_str(&escape(&text[written..]));
+}
+
+/// Renders the text of a document as an HTML page: paragraphs are reflowed, articles, chapters and
+/// sections get anchors listed in a table of contents, and the given folded query terms are
+/// highlighted with links jumping from each match to the next. The page names its canonical URL,
+/// the same whatever the terms, links to the PDF of the document at `pdf_path` and is described by
+/// the beginning of the document for search engines. The text around the document is written in
+/// `locale`.
+pub fn render(document: &PDFdoc, terms: &[String], canonical_url: &str, pdf_path: &str, locale: Locale) -> String {
+    let paragraphs = reflow(&document.content);
+    let total: usize = paragraphs
+        .iter()
+        .map(|paragraph| find_matches(&paragraph.text, terms).len())
+        .sum();
+
+    let mut contents = String::new();
+    let mut text = String::new();
+    let mut match_number = 0;
+    let mut current_page = 1;
+
+    for paragraph in &paragraphs {
+        if paragraph.page != current_page {
+            current_page = paragraph.page;
+            let _ = write!(
+                text,
+                r#"<hr id="page-{current_page}" title="{} {current_page}">"#,
+                locale.text("Page", "Página")
+            );
+        }
+
+        let tag = if paragraph.heading { "h2" } else { "p" };
+        match (&paragraph.anchor, &paragraph.label) {
+            (Some(anchor), Some(label)) => {
+                let _ = write!(contents, r##"<li><a href="#{anchor}">{}</a></li>"##, escape(label));
+                let _ = write!(
+                    text,
+                    r##"<{tag} id="{anchor}"><a class="anchor" href="#{anchor}">#</a> "##
+                );
+            },
+            _ => {
+                let _ = write!(text, "<{tag}>");
+            },
+        }
+        write_highlighted(&mut text, &paragraph.text, terms, &mut match_number, total, locale);
+        let _ = writeln!(text, "</{tag}>");
+    }
+
+    let mut body = String::new();
+    let _ = write!(
+        body,
+        r#"<header>
+    <p><a href="/">{back}</a></p>
+    <h1>{title}</h1>
+    <p>{date} · {category}</p>
+    <p><a href="{pdf_path}">{view_pdf}</a> · <a href="{link}">{original_link}</a></p>
+"#,
+        back = locale.text("Back to search", "Voltar à busca"),
+        title = escape(&document.title),
+        date = document.formatted_date(),
+        category = locale.category_label(document.is_normative),
+        pdf_path = escape(pdf_path),
+        view_pdf = locale.text("View PDF", "Ver PDF"),
+        link = escape(&document.link),
+        original_link = locale.text("Original link", "Link original"),
+    );
+    if !terms.is_empty() {
+        let _ = if total == 0 {
+            writeln!(
+                body,
+                r#"    <nav class="matches">{}</nav>"#,
+                locale.text(
+                    "No matches for the search terms.",
+                    "Nenhuma ocorrência dos termos da busca."
+                )
+            )
+        } else {
+            writeln!(
+                body,
+                r##"    <nav class="matches">{total} {} · <a href="#match-1">{}</a></nav>"##,
+                locale.text("matches", "ocorrências"),
+                locale.text("Go to the first match", "Ir para a primeira ocorrência"),
+            )
+        };
+    }
+    body.push_str("</header>\n");
+    if !contents.is_empty() {
+        let _ = writeln!(
+            body,
+            r#"<nav class="contents"><h2>{}</h2><ul>{contents}</ul></nav>"#,
+            locale.text("Contents", "Sumário")
+        );
+    }
+    let _ = write!(body, r#"<article class="document">{text}</article>"#);
+
+    let head = format!(
+        r#"    <link rel="canonical" href="{}">
+    <meta name="description" content="{}">
+"#,
+        escape(canonical_url),
+        escape(&snippet(&document.content, DESCRIPTION_LENGTH)),
+    );
+    page(locale, &document.title, &head, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "RESOLUÇÃO Nº 1\n\nCAPÍTULO I\nDAS DISPOSIÇÕES\nArt. 1º O trancamen-\nto de matrícula\nserá \
+                           permitido.\n§ 1º O pedido\n\u{c}Art. 2º Revoga-se a norma.\nANEXO I\nArt. 1º Anexo.\n";
+
+    fn document() -> PDFdoc {
+        PDFdoc {
+            id: "abc".to_string(),
+            title: "Resolução nº 1".to_string(),
+            date: 1_554_076_800,
+            content: CONTENT.to_string(),
+            link: "https://sig.unb.br/sigrh/downloadArquivo?idArquivo=1&key=a".to_string(),
+            is_normative: 1,
+            collection: None,
+        }
+    }
+
+    #[test]
+    fn test_reflow_joins_lines_and_anchors_structure() {
+        let paragraphs = reflow(CONTENT);
+        let summary: Vec<_> = paragraphs
+            .iter()
+            .map(|paragraph| {
+                (
+                    paragraph.text.as_str(),
+                    paragraph.page,
+                    paragraph.anchor.as_deref(),
+                    paragraph.label.as_deref(),
+                    paragraph.heading,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                ("RESOLUÇÃO Nº 1", 1, None, None, false),
+                (
+                    "CAPÍTULO I DAS DISPOSIÇÕES",
+                    1,
+                    Some("capitulo-i"),
+                    Some("CAPÍTULO I"),
+                    true
+                ),
+                (
+                    "Art. 1º O trancamento de matrícula será permitido.",
+                    1,
+                    Some("art-1"),
+                    Some("Art. 1"),
+                    false
+                ),
+                ("§ 1º O pedido", 1, None, None, false),
+                ("Art. 2º Revoga-se a norma.", 2, Some("art-2"), Some("Art. 2"), false),
+                ("ANEXO I", 2, Some("anexo-i"), Some("ANEXO I"), true),
+                // The annex restarts the numbering of articles
+                ("Art. 1º Anexo.", 2, Some("art-1-2"), Some("Art. 1"), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reflow_keeps_hyphens_before_capitals_and_skips_empty_pages() {
+        let paragraphs = reflow("\u{c}\u{c}Pró-\nReitoria de Graduação\n\n\n");
+        assert_eq!(paragraphs.len(), 1);
+        assert_eq!(paragraphs[0].text, "Pró- Reitoria de Graduação");
+        assert_eq!(paragraphs[0].page, 3);
+    }
+
+    #[test]
+    fn test_render_links_anchors_and_matches() {
+        let html = render(
+            &document(),
+            &["trancamento".to_string(), "anexo".to_string()],
+            "https://example.org/documents/abc/view",
+            "/documents/abc/pdf?collection=atas",
+            Locale::En,
+        );
+
+        assert!(html.contains(r#"<html lang="en">"#));
+        assert!(html.contains(r##"<li><a href="#capitulo-i">CAPÍTULO I</a></li>"##));
+        assert!(html.contains(r##"<h2 id="capitulo-i"><a class="anchor" href="#capitulo-i">#</a> "##));
+        assert!(html.contains(r##"<p id="art-1-2"><a class="anchor" href="#art-1-2">#</a> "##));
+        assert!(html.contains(r#"<hr id="page-2" title="Page 2">"#));
+
+        // Each match links to the next one, and the last back to the first
+        assert!(html.contains(
+            r##"<a class="match" id="match-1" href="#match-2" title="Next match"><mark>trancamento</mark></a>"##
+        ));
+        assert!(
+            html.contains(
+                r##"<a class="match" id="match-3" href="#match-1" title="Next match"><mark>Anexo</mark></a>"##
+            )
+        );
+        assert!(html.contains("3 matches"));
+        assert!(html.contains(r#"<link rel="canonical" href="https://example.org/documents/abc/view">"#));
+        assert!(html.contains(r#"<a href="/documents/abc/pdf?collection=atas">View PDF</a>"#));
+        assert!(html.contains("Back to search"));
+        assert!(html.contains("01/04/2019 · Normative"));
+    }
+
+    #[test]
+    fn test_render_in_portuguese() {
+        let html = render(
+            &document(),
+            &["perspicaz".to_string()],
+            "https://example.org/",
+            "/documents/abc/pdf",
+            Locale::PtBr,
+        );
+
+        assert!(html.contains(r#"<html lang="pt-BR">"#));
+        assert!(html.contains("Voltar à busca"));
+        assert!(html.contains("01/04/2019 · Normativa"));
+        assert!(html.contains("Nenhuma ocorrência dos termos da busca."));
+        assert!(html.contains(r#"<h2>Sumário</h2>"#));
+        assert!(html.contains(r#"<hr id="page-2" title="Página 2">"#));
+        assert!(!html.contains("Back to search"));
+    }
+}
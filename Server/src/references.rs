@@ -0,0 +1,316 @@
+use crate::api::v1::{Citation, DocumentLink, References};
+use crate::config::Config;
+use crate::corpus::CorpusCache;
+use crate::documents::{DocumentQuery, fetch_document};
+use crate::resilience::Guard;
+use crate::text::fold;
+use crate::{PDFdoc, caching, collections};
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use meilisearch_sdk::client::Client;
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// A resolution, as named in the title of the document publishing it or in a citation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Resolution {
+    /// Council that issued the resolution, folded, e.g. “cepe” or “cad”. Often left out when a
+    /// resolution of the same council is cited.
+    council: Option<String>,
+    number: u32,
+    year: u16,
+}
+
+impl Resolution {
+    /// Whether two mentions may refer to the same resolution. Numbers restart every year and for
+    /// each council, so the council is only compared when both mentions name it.
+    fn matches(&self, other: &Self) -> bool {
+        self.number == other.number
+            && self.year == other.year
+            && (self.council.is_none() || other.council.is_none() || self.council == other.council)
+    }
+
+    /// Returns the resolution as it is usually written, e.g. “Resolução CAD nº 45/2020”.
+    fn label(&self) -> String {
+        match &self.council {
+            Some(council) => format!("Resolução {} nº {}/{}", council.to_uppercase(), self.number, self.year),
+            None => format!("Resolução nº {}/{}", self.number, self.year),
+        }
+    }
+}
+
+/// Matches a resolution in folded text (“resolucao consepe nº 104/2021”, “resolucao do cad n.
+/// 45/20”).
+fn resolution_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(concat!(
+            r"\bresolucao\s+(?:d[oa]\s+)?(?:(?P<council>[a-z]{2,}(?:/[a-z]+)?)\s+)?",
+            r"(?:n[º°o]?\.?|numero)\s*(?P<number>\d{1,5})\s*/\s*(?P<year>\d{4}|\d{2})\b",
+        ))
+        .expect("Invalid Regular Expression for resolutions.")
+    })
+}
+
+/// Returns the resolutions named in a text, in order of appearance and without repetitions.
+fn resolutions(text: &str) -> Vec<Resolution> {
+    let mut found: Vec<Resolution> = Vec::new();
+    for captures in resolution_pattern().captures_iter(&fold(text)) {
+        let (Ok(number), Ok(year)) = (captures["number"].parse::<u32>(), captures["year"].parse::<u16>()) else {
+            continue;
+        };
+        let year = match year {
+            0..=49 => 2000 + year,
+            50..=99 => 1900 + year,
+            _ => year,
+        };
+        let resolution = Resolution {
+            council: captures.name("council").map(|council| council.as_str().to_string()),
+            number,
+            year,
+        };
+        if !found.contains(&resolution) {
+            found.push(resolution);
+        }
+    }
+    found
+}
+
+/// Splits the resolutions named by a document into the one it publishes, named first in its
+/// title, and those it cites.
+fn identify(title: &str, content: &str) -> (Option<Resolution>, Vec<Resolution>) {
+    let mut title_resolutions = resolutions(title).into_iter();
+    let identity = title_resolutions.next();
+    let mut cited: Vec<Resolution> = title_resolutions.collect();
+    for resolution in resolutions(content) {
+        let is_itself = identity.as_ref().is_some_and(|identity| identity.matches(&resolution));
+        if !is_itself && !cited.contains(&resolution) {
+            cited.push(resolution);
+        }
+    }
+    (identity, cited)
+}
+
+/// An indexed document, with the resolution it publishes and those it cites.
+struct Entry {
+    id: String,
+    title: String,
+    collection: String,
+    identity: Option<Resolution>,
+    cited: Vec<Resolution>,
+}
+
+/// The citations between the indexed documents, built along with the corpus statistics.
+#[derive(Default)]
+pub struct ReferenceGraph {
+    entries: Vec<Entry>,
+}
+
+impl ReferenceGraph {
+    /// Adds a document of a collection to the graph.
+    pub fn add(&mut self, id: String, title: String, collection: &str, content: &str) {
+        let (identity, cited) = identify(&title, content);
+        self.entries.push(Entry {
+            id,
+            title,
+            collection: collection.to_string(),
+            identity,
+            cited,
+        });
+    }
+
+    /// Returns the resolutions the document cites, with the indexed documents publishing them,
+    /// and the documents citing it. The citations of the document are read from the document
+    /// itself, so that they are up to date even if the graph is not.
+    fn references<'a>(&'a self, document: &'a PDFdoc) -> References<'a> {
+        let link = |entry: &'a Entry| DocumentLink {
+            id: &entry.id,
+            title: &entry.title,
+            collection: &entry.collection,
+        };
+        let (identity, cited) = identify(&document.title, &document.content);
+
+        let cites = cited
+            .iter()
+            .map(|resolution| Citation {
+                citation: resolution.label(),
+                documents: self
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.id != document.id)
+                    .filter(|entry| {
+                        entry
+                            .identity
+                            .as_ref()
+                            .is_some_and(|identity| identity.matches(resolution))
+                    })
+                    .map(link)
+                    .collect(),
+            })
+            .collect();
+
+        let cited_by = identity
+            .map(|identity| {
+                self.entries
+                    .iter()
+                    .filter(|entry| entry.id != document.id)
+                    .filter(|entry| entry.cited.iter().any(|resolution| resolution.matches(&identity)))
+                    .map(link)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        References { cites, cited_by }
+    }
+}
+
+/// Returns the resolutions a document cites, resolved to the indexed documents publishing them,
+/// and the documents citing the resolution it publishes. Citations are found by matching the
+/// resolution numbers and years mentioned in the documents against those in the indexed titles.
+pub async fn references(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<DocumentQuery>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    corpus: web::Data<CorpusCache>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let id = path.into_inner();
+    let collection = collections::resolve_one(&config, params.collection.as_deref())?;
+    let document = fetch_document(&client, &guard, &collection, &id).await?;
+    let corpus = corpus.get(&client, &guard, &config).await?;
+
+    // The citing documents depend on the whole corpus, so the ETag is computed from the response
+    let body = serde_json::to_string(&corpus.references.references(&document))?;
+    Ok(caching::respond(
+        &req,
+        caching::etag(&body),
+        config.cache_max_age,
+        || HttpResponse::Ok().content_type("application/json").body(body),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn resolution(council: Option<&str>, number: u32, year: u16) -> Resolution {
+        Resolution {
+            council: council.map(str::to_string),
+            number,
+            year,
+        }
+    }
+
+    fn document(id: &str, title: &str, content: &str) -> PDFdoc {
+        PDFdoc {
+            id: id.to_string(),
+            title: title.to_string(),
+            date: 0,
+            content: content.to_string(),
+            link: String::new(),
+            is_normative: 1,
+            collection: None,
+        }
+    }
+
+    #[test]
+    fn test_resolutions() {
+        let cases = [
+            (
+                "conforme Resolução CONSEPE nº 104/2021",
+                vec![resolution(Some("consepe"), 104, 2021)],
+            ),
+            (
+                "revoga a Resolução do CAD n. 45/20 e a Resolução da CEPE/UnB Nº 3 / 1998",
+                vec![resolution(Some("cad"), 45, 2020), resolution(Some("cepe/unb"), 3, 1998)],
+            ),
+            ("nos termos da Resolução nº 12/99", vec![resolution(None, 12, 1999)]),
+            ("RESOLUÇÃO NÚMERO 7/2019", vec![resolution(None, 7, 2019)]),
+            (
+                "Resolução n° 5/2020 e Resolução no 5/2020",
+                vec![resolution(None, 5, 2020)],
+            ),
+            // Not resolutions: no number sign, another kind of act, or no year
+            (
+                "Resolução CONSEPE 104/2021, Portaria nº 10/2020, Resolução nº 8",
+                vec![],
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(resolutions(text), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_resolution_matches() {
+        let cad = resolution(Some("cad"), 45, 2020);
+        assert!(cad.matches(&resolution(None, 45, 2020)));
+        assert!(cad.matches(&resolution(Some("cad"), 45, 2020)));
+        assert!(!cad.matches(&resolution(Some("consepe"), 45, 2020)));
+        assert!(!cad.matches(&resolution(Some("cad"), 45, 2021)));
+        assert_eq!(cad.label(), "Resolução CAD nº 45/2020");
+        assert_eq!(resolution(None, 45, 2020).label(), "Resolução nº 45/2020");
+    }
+
+    #[test]
+    fn test_identify() {
+        let (identity, cited) = identify(
+            "Resolução CONSEPE nº 104/2021, que altera a Resolução CONSEPE nº 90/2019",
+            "A Resolução nº 104/2021 altera a Resolução CONSEPE nº 90/2019 e revoga a Resolução CAD nº 1/2000.",
+        );
+        assert_eq!(identity, Some(resolution(Some("consepe"), 104, 2021)));
+        assert_eq!(
+            cited,
+            [resolution(Some("consepe"), 90, 2019), resolution(Some("cad"), 1, 2000)]
+        );
+
+        assert_eq!(identify("Edital de seleção", "Sem citações."), (None, vec![]));
+    }
+
+    #[test]
+    fn test_references() {
+        let mut graph = ReferenceGraph::default();
+        graph.add(
+            "a".to_string(),
+            "Resolução CONSEPE nº 90/2019".to_string(),
+            "sigrh",
+            "Dispõe sobre os estágios.",
+        );
+        graph.add(
+            "b".to_string(),
+            "Resolução CONSEPE nº 104/2021".to_string(),
+            "sigrh",
+            "Altera a Resolução nº 90/2019.",
+        );
+        graph.add(
+            "c".to_string(),
+            "Resolução CAD nº 90/2019".to_string(),
+            "atas",
+            "Conforme a Resolução CONSEPE nº 104/2021.",
+        );
+
+        let document = document(
+            "b",
+            "Resolução CONSEPE nº 104/2021",
+            "Altera a Resolução nº 90/2019 e a Resolução nº 3/1990.",
+        );
+        assert_eq!(
+            serde_json::to_value(graph.references(&document)).unwrap(),
+            json!({
+                "cites": [
+                    {
+                        "citation": "Resolução nº 90/2019",
+                        "documents": [
+                            {"id": "a", "title": "Resolução CONSEPE nº 90/2019", "collection": "sigrh"},
+                            {"id": "c", "title": "Resolução CAD nº 90/2019", "collection": "atas"},
+                        ],
+                    },
+                    {"citation": "Resolução nº 3/1990", "documents": []},
+                ],
+                "cited_by": [{"id": "c", "title": "Resolução CAD nº 90/2019", "collection": "atas"}],
+            })
+        );
+    }
+}
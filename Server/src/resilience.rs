@@ -0,0 +1,644 @@
+//! Protection against a slow or failing Meilisearch. Reads go through a [`Guard`], which gives
+//! up on calls taking longer than the configured timeout and retries those that failed for a
+//! reason that may be transient. Once too many calls in a row have failed, the circuit opens: for a
+//! while Meilisearch is not called at all, and searches are answered right away with the latest
+//! results of the same search, or with a 503. The state of the guard is exposed on `/ready` and
+//! `/metrics`.
+
+use crate::config::Resilience;
+use crate::{PDFdoc, meilisearch_error};
+use actix_rt::time::{sleep, timeout};
+use actix_web::http::{StatusCode, header};
+use actix_web::{Error, HttpResponse, web};
+use log::warn;
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::errors::{Error as MeilisearchSdkError, ErrorType};
+use meilisearch_sdk::search::SearchResult;
+use serde_json::json;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// State of the circuit breaker.
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    /// Calls go through. Counts the calls that failed in a row.
+    Closed { failures: u32 },
+    /// Calls are rejected until the open duration is over.
+    Open { since: Instant },
+    /// A single call is let through to find out whether Meilisearch recovered. Should it never
+    /// finish, e.g. because the search was cancelled, another one is let through after the open
+    /// duration.
+    HalfOpen { probe_started: Instant },
+}
+
+impl Circuit {
+    /// Name of the state, as shown on `/ready`.
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Closed { .. } => "closed",
+            Self::Open { .. } => "open",
+            Self::HalfOpen { .. } => "half-open",
+        }
+    }
+
+    /// Value of the state in the metrics: 0 when closed, 1 when half-open and 2 when open.
+    const fn gauge(self) -> u8 {
+        match self {
+            Self::Closed { .. } => 0,
+            Self::HalfOpen { .. } => 1,
+            Self::Open { .. } => 2,
+        }
+    }
+}
+
+/// Counters exposed on `/metrics`.
+#[derive(Default)]
+struct Metrics {
+    calls: AtomicU64,
+    failures: AtomicU64,
+    timeouts: AtomicU64,
+    retries: AtomicU64,
+    rejected: AtomicU64,
+    stale: AtomicU64,
+}
+
+/// Results of a search, kept to be served while Meilisearch is unavailable.
+struct StaleEntry {
+    stored: Instant,
+    hits: Arc<[SearchResult<PDFdoc>]>,
+}
+
+/// Timeouts, retries and circuit breaker shared by every call to Meilisearch.
+pub struct Guard {
+    policy: Resilience,
+    circuit: Mutex<Circuit>,
+    stale: Mutex<HashMap<u64, StaleEntry>>,
+    metrics: Metrics,
+}
+
+/// Tells whether a failed call may succeed if made again: the server could not be reached, or it
+/// failed on its side. Invalid requests fail the same way every time.
+fn is_transient(e: &MeilisearchSdkError) -> bool {
+    match e {
+        MeilisearchSdkError::UnreachableServer | MeilisearchSdkError::HttpError(_) => true,
+        MeilisearchSdkError::MeilisearchCommunication(e) => e.status_code >= 500,
+        MeilisearchSdkError::Meilisearch(e) => e.error_type == ErrorType::Internal,
+        _ => false,
+    }
+}
+
+/// Tells whether an error was returned because Meilisearch is unavailable.
+pub fn is_unavailable(e: &Error) -> bool {
+    e.as_response_error().status_code() == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// Returns the key the results of a search are kept under, from everything the results depend on.
+pub fn key(search: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    search.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Guard {
+    pub fn new(policy: Resilience) -> Self {
+        Self {
+            policy,
+            circuit: Mutex::new(Circuit::Closed { failures: 0 }),
+            stale: Mutex::new(HashMap::new()),
+            metrics: Metrics::default(),
+        }
+    }
+
+    fn open_duration(&self) -> Duration {
+        Duration::from_secs(self.policy.open_duration)
+    }
+
+    /// Returns the current state of the circuit.
+    fn circuit(&self) -> Circuit {
+        *self.circuit.lock().expect("Circuit breaker lock poisoned.")
+    }
+
+    /// Returns the name of the current state of the circuit: closed, half-open or open.
+    pub fn circuit_state(&self) -> &'static str {
+        self.circuit().name()
+    }
+
+    /// Tells whether calls are rejected right now, without changing the state of the circuit: it is
+    /// open, or half-open with its probe still running, and the open duration is not over.
+    fn rejects(&self) -> bool {
+        match self.circuit() {
+            Circuit::Closed { .. } => false,
+            Circuit::Open { since } | Circuit::HalfOpen { probe_started: since } => {
+                since.elapsed() < self.open_duration()
+            },
+        }
+    }
+
+    /// Tells whether a call may go through, turning an open circuit half-open once the open
+    /// duration is over.
+    fn allow(&self) -> bool {
+        let mut circuit = self.circuit.lock().expect("Circuit breaker lock poisoned.");
+        match *circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { since } | Circuit::HalfOpen { probe_started: since } => {
+                if since.elapsed() < self.open_duration() {
+                    return false;
+                }
+                *circuit = Circuit::HalfOpen {
+                    probe_started: Instant::now(),
+                };
+                true
+            },
+        }
+    }
+
+    /// Records the outcome of a call that went through.
+    fn record(&self, succeeded: bool) {
+        let mut circuit = self.circuit.lock().expect("Circuit breaker lock poisoned.");
+        *circuit = match (*circuit, succeeded) {
+            (_, true) => Circuit::Closed { failures: 0 },
+            (Circuit::Closed { failures }, false) if failures + 1 < self.policy.failure_threshold => {
+                Circuit::Closed { failures: failures + 1 }
+            },
+            (Circuit::Open { since }, false) => Circuit::Open { since },
+            (_, false) => {
+                warn!(
+                    "Meilisearch is failing, not calling it for {} seconds.",
+                    self.policy.open_duration
+                );
+                Circuit::Open { since: Instant::now() }
+            },
+        };
+    }
+
+    /// Returns the error given while Meilisearch is unavailable, telling clients when to retry.
+    fn unavailable(&self) -> Error {
+        let retry_after = match self.circuit() {
+            Circuit::Open { since } => self.open_duration().saturating_sub(since.elapsed()),
+            _ => self.open_duration(),
+        };
+        actix_web::error::InternalError::from_response(
+            "Search is temporarily unavailable",
+            HttpResponse::ServiceUnavailable()
+                .insert_header((header::RETRY_AFTER, retry_after.as_secs().max(1)))
+                .body("Search is temporarily unavailable"),
+        )
+        .into()
+    }
+
+    /// Makes a read-only call to Meilisearch. The call is made again, waiting longer each time, if
+    /// it failed for a reason that may be transient, and given up once the configured timeout is
+    /// over, whatever attempt it is at. Fails with a service unavailable error when every attempt
+    /// failed that way or when the circuit is open, and with an internal server error when
+    /// Meilisearch rejected the call.
+    pub async fn call<T, F, Fut>(&self, operation: F) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, MeilisearchSdkError>>,
+    {
+        self.call_with(operation, meilisearch_error).await
+    }
+
+    /// Makes a read-only call to Meilisearch like [`Guard::call`], turning the errors of calls
+    /// Meilisearch rejected into a response with `rejected`, e.g. to answer a missing document
+    /// with a 404.
+    pub async fn call_with<T, F, Fut>(
+        &self,
+        operation: F,
+        rejected: impl FnOnce(MeilisearchSdkError) -> Error,
+    ) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, MeilisearchSdkError>>,
+    {
+        if !self.allow() {
+            self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(self.unavailable());
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(self.policy.timeout_ms);
+        let mut delay = Duration::from_millis(self.policy.retry_delay_ms);
+        for attempt in 0..=self.policy.retries {
+            if attempt > 0 {
+                // There is no point in waiting for an attempt that could not finish in time
+                if deadline.saturating_duration_since(Instant::now()) <= delay {
+                    break;
+                }
+                self.metrics.retries.fetch_add(1, Ordering::Relaxed);
+                sleep(delay).await;
+                delay *= 2;
+            }
+
+            self.metrics.calls.fetch_add(1, Ordering::Relaxed);
+            match timeout(deadline.saturating_duration_since(Instant::now()), operation()).await {
+                Ok(Ok(value)) => {
+                    self.record(true);
+                    return Ok(value);
+                },
+                // Meilisearch answered, so it is up even if it rejected the call
+                Ok(Err(e)) if !is_transient(&e) => {
+                    self.record(true);
+                    return Err(rejected(e));
+                },
+                Ok(Err(e)) => {
+                    self.metrics.failures.fetch_add(1, Ordering::Relaxed);
+                    warn!("Meilisearch call failed (attempt {}): {e}", attempt + 1);
+                },
+                Err(_) => {
+                    self.metrics.timeouts.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "Meilisearch call timed out after {} ms (attempt {})",
+                        self.policy.timeout_ms,
+                        attempt + 1
+                    );
+                    break;
+                },
+            }
+        }
+
+        self.record(false);
+        Err(self.unavailable())
+    }
+
+    /// Keeps the results of a search, to be served while Meilisearch is unavailable. The oldest
+    /// results are dropped once the configured number of searches is kept. The hits are shared
+    /// with the response rather than copied.
+    pub fn remember(&self, key: u64, hits: &Arc<[SearchResult<PDFdoc>]>) {
+        if self.policy.stale_results == 0 {
+            return;
+        }
+        let mut stale = self.stale.lock().expect("Stale results lock poisoned.");
+        if stale.len() >= self.policy.stale_results && !stale.contains_key(&key) {
+            let oldest = stale.iter().min_by_key(|(_, entry)| entry.stored).map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                stale.remove(&oldest);
+            }
+        }
+        stale.insert(
+            key,
+            StaleEntry {
+                stored: Instant::now(),
+                hits: Arc::clone(hits),
+            },
+        );
+    }
+
+    /// Returns the latest results of a search, if they were kept.
+    pub fn stale(&self, key: u64) -> Option<Arc<[SearchResult<PDFdoc>]>> {
+        let hits = self
+            .stale
+            .lock()
+            .expect("Stale results lock poisoned.")
+            .get(&key)
+            .map(|entry| Arc::clone(&entry.hits));
+        if hits.is_some() {
+            self.metrics.stale.fetch_add(1, Ordering::Relaxed);
+        }
+        hits
+    }
+}
+
+/// Tells whether the server can answer searches: the circuit does not reject calls and Meilisearch
+/// reports itself available within the configured timeout. Answers with a 503 otherwise, so that
+/// load balancers stop sending traffic. The probe goes around the circuit breaker, so that it
+/// neither counts as a call nor changes the state of the circuit.
+pub async fn ready(client: web::Data<Client>, guard: web::Data<Guard>) -> HttpResponse {
+    let available = !guard.rejects()
+        && timeout(Duration::from_millis(guard.policy.timeout_ms), client.health())
+            .await
+            .is_ok_and(|health| health.is_ok_and(|health| health.status == "available"));
+
+    let body = json!({
+        "ready": available,
+        "circuit": guard.circuit_state(),
+    });
+    if available {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Exposes the counters of the calls made to Meilisearch and the state of the circuit in the
+/// Prometheus text format.
+pub async fn metrics(guard: web::Data<Guard>) -> HttpResponse {
+    let counters = [
+        (
+            "meilisearch_calls_total",
+            "Calls made to Meilisearch, retries included.",
+            &guard.metrics.calls,
+        ),
+        (
+            "meilisearch_failures_total",
+            "Calls to Meilisearch that failed for a transient reason.",
+            &guard.metrics.failures,
+        ),
+        (
+            "meilisearch_timeouts_total",
+            "Calls to Meilisearch given up after the timeout.",
+            &guard.metrics.timeouts,
+        ),
+        (
+            "meilisearch_retries_total",
+            "Calls to Meilisearch made again.",
+            &guard.metrics.retries,
+        ),
+        (
+            "meilisearch_rejected_total",
+            "Calls not made because the circuit was open.",
+            &guard.metrics.rejected,
+        ),
+        (
+            "search_stale_responses_total",
+            "Searches answered with earlier results while Meilisearch was unavailable.",
+            &guard.metrics.stale,
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, help, counter) in counters {
+        let _ = write!(
+            body,
+            "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+            counter.load(Ordering::Relaxed)
+        );
+    }
+    let _ = write!(
+        body,
+        "# HELP meilisearch_circuit_state State of the circuit breaker: 0 closed, 1 half-open, 2 open.\n\
+         # TYPE meilisearch_circuit_state gauge\nmeilisearch_circuit_state {}\n",
+        guard.circuit().gauge()
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    /// A policy failing fast, so that the tests do not wait on retries.
+    fn policy() -> Resilience {
+        Resilience {
+            timeout_ms: 1000,
+            retries: 0,
+            retry_delay_ms: 1,
+            failure_threshold: 2,
+            open_duration: 30,
+            stale_results: 2,
+        }
+    }
+
+    /// Returns a call counting how many times it is made, failing with the given error.
+    fn failing(
+        calls: &AtomicU32,
+        error: fn() -> MeilisearchSdkError,
+    ) -> impl Fn() -> std::future::Ready<Result<(), MeilisearchSdkError>> + '_ {
+        move || {
+            calls.fetch_add(1, Ordering::Relaxed);
+            std::future::ready(Err(error()))
+        }
+    }
+
+    #[actix_rt::test]
+    async fn circuit_opens_after_failures_in_a_row() {
+        let guard = Guard::new(policy());
+        let calls = AtomicU32::new(0);
+
+        assert!(
+            guard
+                .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+                .await
+                .is_err()
+        );
+        assert_eq!(guard.circuit_state(), "closed");
+        assert!(
+            guard
+                .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+                .await
+                .is_err()
+        );
+        assert_eq!(guard.circuit_state(), "open");
+
+        // Meilisearch is no longer called while the circuit is open
+        let error = guard
+            .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+            .await
+            .unwrap_err();
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert_eq!(guard.metrics.rejected.load(Ordering::Relaxed), 1);
+        assert!(is_unavailable(&error));
+        let response = error.as_response_error().error_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let retry_after: u64 = response
+            .headers()
+            .get(header::RETRY_AFTER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=30).contains(&retry_after));
+    }
+
+    #[actix_rt::test]
+    async fn success_resets_the_failure_count() {
+        let guard = Guard::new(policy());
+        let calls = AtomicU32::new(0);
+
+        assert!(
+            guard
+                .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            guard.call(|| async { Ok::<_, MeilisearchSdkError>(1) }).await.unwrap(),
+            1
+        );
+        assert!(
+            guard
+                .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+                .await
+                .is_err()
+        );
+        assert_eq!(guard.circuit_state(), "closed");
+    }
+
+    #[actix_rt::test]
+    async fn half_open_circuit_lets_a_probe_through() {
+        let guard = Guard::new(Resilience {
+            failure_threshold: 1,
+            open_duration: 0,
+            ..policy()
+        });
+        let calls = AtomicU32::new(0);
+
+        assert!(
+            guard
+                .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+                .await
+                .is_err()
+        );
+        assert_eq!(guard.circuit_state(), "open");
+
+        // The open duration is over, so the next call is a probe, and its failure opens the circuit again
+        assert!(guard.allow());
+        assert_eq!(guard.circuit_state(), "half-open");
+        guard.record(false);
+        assert_eq!(guard.circuit_state(), "open");
+
+        assert_eq!(
+            guard.call(|| async { Ok::<_, MeilisearchSdkError>(1) }).await.unwrap(),
+            1
+        );
+        assert_eq!(guard.circuit_state(), "closed");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[actix_rt::test]
+    async fn transient_failures_are_retried() {
+        let guard = Guard::new(Resilience { retries: 2, ..policy() });
+        let calls = AtomicU32::new(0);
+
+        let error = guard
+            .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+            .await
+            .unwrap_err();
+        assert!(is_unavailable(&error));
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(guard.metrics.retries.load(Ordering::Relaxed), 2);
+        assert_eq!(guard.metrics.failures.load(Ordering::Relaxed), 3);
+
+        // A call succeeding on a retry succeeds
+        let attempts = AtomicU32::new(0);
+        let value = guard
+            .call(|| {
+                let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    match attempt {
+                        0 => Err(MeilisearchSdkError::UnreachableServer),
+                        _ => Ok(attempt),
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(guard.circuit_state(), "closed");
+    }
+
+    #[actix_rt::test]
+    async fn rejected_calls_are_not_retried() {
+        let guard = Guard::new(Resilience {
+            retries: 2,
+            failure_threshold: 1,
+            ..policy()
+        });
+        let calls = AtomicU32::new(0);
+
+        let error = guard
+            .call_with(failing(&calls, || MeilisearchSdkError::InvalidRequest), |_| {
+                actix_web::error::ErrorNotFound("Document not found")
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(error.as_response_error().status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        // Meilisearch answered, so the circuit stays closed
+        assert_eq!(guard.circuit_state(), "closed");
+    }
+
+    #[actix_rt::test]
+    async fn timeout_covers_every_attempt() {
+        let guard = Guard::new(Resilience {
+            timeout_ms: 100,
+            retries: 10,
+            ..policy()
+        });
+        let calls = AtomicU32::new(0);
+
+        let started = Instant::now();
+        let error = guard
+            .call(|| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                async {
+                    sleep(Duration::from_millis(40)).await;
+                    Err::<(), _>(MeilisearchSdkError::UnreachableServer)
+                }
+            })
+            .await
+            .unwrap_err();
+        assert!(is_unavailable(&error));
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert!(calls.load(Ordering::Relaxed) <= 3);
+        assert_eq!(guard.metrics.timeouts.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn stale_results_keep_the_latest_searches() {
+        let guard = Guard::new(policy());
+        let hits: Arc<[SearchResult<PDFdoc>]> = Arc::from(Vec::new());
+
+        assert!(guard.stale(1).is_none());
+        guard.remember(1, &hits);
+        assert!(Arc::ptr_eq(&guard.stale(1).unwrap(), &hits));
+        assert_eq!(guard.metrics.stale.load(Ordering::Relaxed), 1);
+
+        // The oldest search is dropped once the configured number of searches is kept
+        guard.remember(2, &hits);
+        guard.remember(3, &hits);
+        assert!(guard.stale(1).is_none());
+        assert!(guard.stale(2).is_some());
+        assert!(guard.stale(3).is_some());
+
+        let disabled = Guard::new(Resilience {
+            stale_results: 0,
+            ..policy()
+        });
+        disabled.remember(1, &hits);
+        assert!(disabled.stale(1).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn readiness_probe_leaves_the_circuit_alone() {
+        let mock = crate::mock_meilisearch::MockMeilisearch::start();
+        let client = web::Data::new(Client::new(&mock.url, Some(crate::mock_meilisearch::API_KEY)));
+        let guard = web::Data::new(Guard::new(policy()));
+        let probe = || async {
+            let response = ready(client.clone(), guard.clone()).await;
+            let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        assert_eq!(probe().await, json!({ "ready": true, "circuit": "closed" }));
+
+        // While the circuit is open, Meilisearch is not asked
+        let calls = AtomicU32::new(0);
+        for _ in 0..2 {
+            assert!(
+                guard
+                    .call(failing(&calls, || MeilisearchSdkError::UnreachableServer))
+                    .await
+                    .is_err()
+            );
+        }
+        assert_eq!(probe().await, json!({ "ready": false, "circuit": "open" }));
+
+        // Once the open duration is over, the probe does not take the place of the next call
+        *guard.circuit.lock().unwrap() = Circuit::Open {
+            since: Instant::now() - guard.open_duration(),
+        };
+        assert_eq!(probe().await, json!({ "ready": true, "circuit": "open" }));
+        assert_eq!(guard.metrics.calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::analytics::Analytics;
+use crate::config::{Config, LiveSettings};
+use crate::documents::document_path;
+use crate::html::{escape, page};
+use crate::i18n::Locale;
+use crate::query::{SortOrder, normalize};
+use crate::resilience::Guard;
+use crate::text::snippet;
+use crate::{PDFdoc, SEARCH_LIMIT, SearchQueryWrapper, run_search};
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use meilisearch_sdk::client::Client;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Number of characters of the content shown under each result.
+const SNIPPET_LENGTH: usize = 300;
+
+/// Query parameters of the results page, on top of those of the search endpoint.
+#[derive(Deserialize, Debug)]
+pub struct PageParams {
+    /// Page of results, starting at 1.
+    page: Option<usize>,
+}
+
+/// Parameters of the links to the other pages of results.
+#[derive(Serialize)]
+struct PageLink<'a> {
+    q: &'a str,
+    collection: Option<&'a str>,
+    sort: SortOrder,
+    from: Option<&'a str>,
+    to: Option<&'a str>,
+    page: usize,
+}
+
+/// Writes the search form, filled with the current parameters.
+fn write_form(body: &mut String, query: Option<&SearchQueryWrapper>, config: &Config, locale: Locale) {
+    let q = query.map_or("", |query| query.q.as_str());
+    let sort = query.map_or(SortOrder::Relevance, |query| query.sort);
+    let from = query.and_then(|query| query.from.as_deref()).unwrap_or_default();
+    let to = query.and_then(|query| query.to.as_deref()).unwrap_or_default();
+    let selected = |is_selected: bool| if is_selected { " selected" } else { "" };
+
+    let _ = write!(
+        body,
+        r#"<form class="search" action="/buscar" method="get" role="search">
+    <p><label for="q">{search}</label> <input type="search" id="q" name="q" value="{q}"></p>
+"#,
+        search = locale.text("Search", "Buscar"),
+        q = escape(q),
+    );
+
+    if config.collections.len() > 1 {
+        let current = query
+            .and_then(|query| query.collection.as_deref())
+            .unwrap_or(&config.default_collection);
+        let _ = write!(
+            body,
+            r#"    <p><label for="collection">{}</label> <select id="collection" name="collection">
+        <option value="*"{}>{}</option>
+"#,
+            locale.text("Collection", "Coleção"),
+            selected(current == "*"),
+            locale.text("All", "Todas"),
+        );
+        for name in config.collections.keys() {
+            let _ = writeln!(
+                body,
+                r#"        <option value="{name}"{}>{name}</option>"#,
+                selected(current == name),
+                name = escape(name),
+            );
+        }
+        body.push_str("    </select></p>\n");
+    }
+
+    let _ = write!(
+        body,
+        r#"    <p><label for="sort">{sort_by}</label> <select id="sort" name="sort">
+        <option value="relevance"{relevance}>{relevance_label}</option>
+        <option value="newest"{newest}>{newest_label}</option>
+        <option value="oldest"{oldest}>{oldest_label}</option>
+    </select></p>
+    <p><label for="from">{from_label}</label> <input type="date" id="from" name="from" value="{from}">
+    <label for="to">{to_label}</label> <input type="date" id="to" name="to" value="{to}"></p>
+    <p><button type="submit">{search}</button></p>
+</form>
+"#,
+        sort_by = locale.text("Sort by", "Ordenar por"),
+        relevance_label = locale.text("Relevance", "Relevância"),
+        newest_label = locale.text("Newest first", "Mais recentes"),
+        oldest_label = locale.text("Oldest first", "Mais antigos"),
+        from_label = locale.text("From", "De"),
+        to_label = locale.text("To", "Até"),
+        search = locale.text("Search", "Buscar"),
+        relevance = selected(sort == SortOrder::Relevance),
+        newest = selected(sort == SortOrder::Newest),
+        oldest = selected(sort == SortOrder::Oldest),
+        from = escape(from),
+        to = escape(to),
+    );
+}
+
+/// Writes a result of the search, linking to the reader view with the search terms highlighted.
+fn write_result(body: &mut String, document: &PDFdoc, q: &str, config: &Config, locale: Locale) {
+    let collection = document.collection.as_deref();
+    let _ = write!(
+        body,
+        r#"<li>
+    <h2><a href="{view_path}">{title}</a></h2>
+    <p class="details">{date} · {category}"#,
+        view_path = escape(&document_path(config, collection, &document.id, "view", Some(q))),
+        title = escape(&document.title),
+        date = document.formatted_date(),
+        category = locale.category_label(document.is_normative),
+    );
+    if let Some(collection) = &document.collection {
+        let _ = write!(body, " · {}", escape(collection));
+    }
+    let _ = write!(
+        body,
+        r#"</p>
+    <p>{snippet}</p>
+    <p><a href="{pdf_path}">{view_pdf}</a></p>
+</li>
+"#,
+        snippet = escape(&snippet(&document.content, SNIPPET_LENGTH)),
+        pdf_path = escape(&document_path(config, collection, &document.id, "pdf", None)),
+        view_pdf = locale.text("View PDF", "Ver PDF"),
+    );
+}
+
+/// Renders the search results as a plain HTML page, for clients without JavaScript such as screen
+/// readers, text browsers and kiosks. Accepts the same parameters as the search endpoint plus
+/// `page`, and runs the same search. Sorting, filtering and paging are done with the form and
+/// links, so the page works without any script. The page is written in the language requested,
+/// English by default.
+#[allow(clippy::too_many_arguments)]
+pub async fn results_page(
+    req: HttpRequest,
+    query: Option<web::Query<SearchQueryWrapper>>,
+    params: web::Query<PageParams>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+    settings: web::Data<LiveSettings>,
+    analytics: web::Data<Option<Analytics>>,
+) -> Result<HttpResponse, Error> {
+    let settings = settings.get();
+    let locale = Locale::requested(&req).unwrap_or_default();
+    let title = locale.text("Document Search", "Busca de documentos");
+    let query = query
+        .map(web::Query::into_inner)
+        .filter(|query| !query.q.trim().is_empty());
+    let page_number = params.page.unwrap_or(1).max(1);
+    // One more hit than shown is fetched, so the last one must be addressable too
+    let offset = (page_number - 1)
+        .checked_mul(SEARCH_LIMIT)
+        .filter(|offset| offset.checked_add(SEARCH_LIMIT + 1).is_some())
+        .ok_or_else(|| actix_web::error::ErrorBadRequest("Invalid page number"))?;
+
+    let mut body = format!("<header>\n<h1>{title}</h1>\n");
+    write_form(&mut body, query.as_ref(), &config, locale);
+    body.push_str("</header>\n<main>\n");
+
+    let Some(query) = query else {
+        body.push_str("</main>");
+        return Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(page(locale, title, "", &body)));
+    };
+
+    // One more hit than shown tells whether there is a next page
+    let searched = run_search(
+        &query,
+        &client,
+        &guard,
+        &config,
+        &settings,
+        analytics.as_ref().as_ref(),
+        offset,
+        SEARCH_LIMIT + 1,
+    )
+    .await?;
+    let has_next = searched.hits.len() > SEARCH_LIMIT;
+    let hits = &searched.hits[..searched.hits.len().min(SEARCH_LIMIT)];
+
+    let q = escape(&query.q);
+    let _ = if normalize(&query.q, settings.max_query_length).chars().count() < settings.min_query_length {
+        match locale {
+            Locale::PtBr => writeln!(
+                body,
+                r#"<p role="status">Digite ao menos {} caracteres para buscar.</p>"#,
+                settings.min_query_length
+            ),
+            Locale::En => writeln!(
+                body,
+                r#"<p role="status">Type at least {} characters to search.</p>"#,
+                settings.min_query_length
+            ),
+        }
+    } else if hits.is_empty() {
+        match locale {
+            Locale::PtBr => writeln!(body, r#"<p role="status">Nenhum documento encontrado para “{q}”.</p>"#),
+            Locale::En => writeln!(body, r#"<p role="status">No documents found for “{q}”.</p>"#),
+        }
+    } else {
+        let (first, last) = (offset + 1, offset + hits.len());
+        match locale {
+            Locale::PtBr => writeln!(body, r#"<p role="status">Resultados {first} a {last} para “{q}”.</p>"#),
+            Locale::En => writeln!(body, r#"<p role="status">Results {first} to {last} for “{q}”.</p>"#),
+        }
+    };
+    if searched.stale {
+        let _ = writeln!(
+            body,
+            r#"<p role="status">{}</p>"#,
+            locale.text(
+                "Search is temporarily unavailable, these are earlier results of the same search.",
+                "A busca está temporariamente indisponível, estes são resultados anteriores da mesma busca."
+            )
+        );
+    }
+
+    if !hits.is_empty() {
+        let _ = writeln!(body, r#"<ol class="results" start="{}">"#, offset + 1);
+        for hit in hits {
+            write_result(&mut body, &hit.result, &query.q, &config, locale);
+        }
+        body.push_str("</ol>\n");
+    }
+
+    let link = |page: usize| {
+        let parameters = serde_urlencoded::to_string(PageLink {
+            q: &query.q,
+            collection: query.collection.as_deref(),
+            sort: query.sort,
+            from: query.from.as_deref(),
+            to: query.to.as_deref(),
+            page,
+        })
+        .unwrap_or_default();
+        format!("/buscar?{}", escape(&parameters))
+    };
+    if page_number > 1 || has_next {
+        let _ = write!(
+            body,
+            r#"<nav class="pagination" aria-label="{}">"#,
+            locale.text("Pages of results", "Páginas de resultados")
+        );
+        if page_number > 1 {
+            let _ = write!(
+                body,
+                r#"<a rel="prev" href="{}">{}</a>"#,
+                link(page_number - 1),
+                locale.text("Previous page", "Página anterior")
+            );
+        }
+        if has_next {
+            let _ = write!(
+                body,
+                r#" <a rel="next" href="{}">{}</a>"#,
+                link(page_number + 1),
+                locale.text("Next page", "Próxima página")
+            );
+        }
+        body.push_str("</nav>\n");
+    }
+    body.push_str("</main>");
+
+    Ok(HttpResponse::Ok().content_type("text/html; charset=utf-8").body(page(
+        locale,
+        &format!("{} - {title}", query.q),
+        "",
+        &body,
+    )))
+}
@@ -0,0 +1,274 @@
+//! Sitemaps listing the page of every document, so that search engines can find the resolutions
+//! without running the search page. `/sitemap.xml` is an index pointing to `/sitemaps/{n}.xml`
+//! for the default collection and to `/sitemaps/{collection}/{n}.xml` for the others, each listing
+//! at most [`SITEMAP_SIZE`] documents, as the sitemap protocol allows no more per file.
+
+use crate::caching;
+use crate::collections::{self, Collection};
+use crate::config::Config;
+use crate::documents::document_path;
+use crate::html::escape;
+use crate::resilience::Guard;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use meilisearch_sdk::client::Client;
+use meilisearch_sdk::documents::DocumentsQuery;
+use serde::Deserialize;
+use std::fmt::Write;
+
+/// Number of documents listed by each sitemap. The protocol allows up to 50,000.
+const SITEMAP_SIZE: usize = 10_000;
+
+/// Number of documents requested per page from Meilisearch.
+const PAGE_SIZE: usize = 1000;
+
+/// The fields of each indexed document needed to list it.
+#[derive(Deserialize)]
+struct SitemapEntry {
+    id: String,
+    date: i64,
+}
+
+/// Returns the address the public reaches the server at, without a trailing slash: the configured
+/// `public_base_url`, or the scheme and host of the request.
+pub fn base_url(req: &HttpRequest, config: &Config) -> String {
+    match &config.public_base_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => {
+            let connection = req.connection_info();
+            format!("{}://{}", connection.scheme(), connection.host())
+        },
+    }
+}
+
+/// Returns the canonical URL of the page of a document.
+pub fn document_url(base_url: &str, config: &Config, collection: Option<&str>, id: &str) -> String {
+    format!("{base_url}{}", document_path(config, collection, id, "view", None))
+}
+
+/// Returns the path of a sitemap of a collection. Those of the default collection keep the paths
+/// they had before there were several collections.
+fn sitemap_path(config: &Config, collection: &Collection, number: usize) -> String {
+    if collection.name == config.default_collection {
+        format!("/sitemaps/{number}.xml")
+    } else {
+        format!("/sitemaps/{}/{number}.xml", collection.name)
+    }
+}
+
+/// Returns an XML document with the cache headers shared by the sitemaps.
+fn xml_response(body: String, config: &Config) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/xml; charset=utf-8")
+        .insert_header(caching::cache_control(config.cache_max_age))
+        .body(body)
+}
+
+/// Lists the sitemaps, one for every [`SITEMAP_SIZE`] documents of each index, see
+/// [`collections::by_index`].
+pub async fn index(
+    req: HttpRequest,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req, &config);
+    let mut body = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#,
+        "\n",
+    ));
+    for collection in collections::by_index(&config) {
+        let index = client.index(collection.index);
+        let mut query = DocumentsQuery::new(&index);
+        query.with_limit(1).with_fields(["id"]);
+        let total = guard.call(|| query.execute::<serde_json::Value>()).await?.total as usize;
+
+        for number in 0..total.div_ceil(SITEMAP_SIZE).max(1) {
+            let _ = writeln!(
+                body,
+                "  <sitemap><loc>{}{}</loc></sitemap>",
+                escape(&base_url),
+                escape(&sitemap_path(&config, &collection, number))
+            );
+        }
+    }
+    body.push_str("</sitemapindex>\n");
+
+    Ok(xml_response(body, &config))
+}
+
+/// Lists the pages of the documents in one chunk of the default collection.
+pub async fn sitemap(
+    req: HttpRequest,
+    path: web::Path<usize>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let collection = collections::resolve_one(&config, None)?;
+    write_sitemap(&req, &collection, path.into_inner(), &client, &guard, &config).await
+}
+
+/// Lists the pages of the documents in one chunk of a collection. Only the collections listed by
+/// the index have sitemaps.
+pub async fn collection_sitemap(
+    req: HttpRequest,
+    path: web::Path<(String, usize)>,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let (name, number) = path.into_inner();
+    let collection = collections::by_index(&config)
+        .into_iter()
+        .find(|collection| collection.name == name && collection.name != config.default_collection)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Sitemap not found"))?;
+    write_sitemap(&req, &collection, number, &client, &guard, &config).await
+}
+
+/// Lists the pages of the documents in one chunk of the index of a collection, with their
+/// publication date as the last modification.
+async fn write_sitemap(
+    req: &HttpRequest,
+    collection: &Collection<'_>,
+    number: usize,
+    client: &Client,
+    guard: &Guard,
+    config: &Config,
+) -> Result<HttpResponse, Error> {
+    // Numbers too large to address any document cannot name a sitemap
+    let start = number
+        .checked_mul(SITEMAP_SIZE)
+        .filter(|start| start.checked_add(SITEMAP_SIZE).is_some())
+        .ok_or_else(|| actix_web::error::ErrorNotFound("Sitemap not found"))?;
+    let index = client.index(collection.index);
+    let mut entries: Vec<SitemapEntry> = Vec::new();
+    loop {
+        let mut query = DocumentsQuery::new(&index);
+        query
+            .with_offset(start + entries.len())
+            .with_limit(PAGE_SIZE.min(SITEMAP_SIZE - entries.len()))
+            .with_fields(["id", "date"]);
+        let page = guard.call(|| query.execute::<SitemapEntry>()).await?;
+        let fetched = page.results.len();
+        entries.extend(page.results);
+        if fetched == 0 || entries.len() >= SITEMAP_SIZE || start + entries.len() >= page.total as usize {
+            break;
+        }
+    }
+    if entries.is_empty() && number > 0 {
+        return Err(actix_web::error::ErrorNotFound("Sitemap not found"));
+    }
+
+    let base_url = base_url(req, config);
+    let mut body = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#,
+        "\n",
+    ));
+    for entry in &entries {
+        let _ = write!(
+            body,
+            "  <url><loc>{}</loc>",
+            escape(&document_url(&base_url, config, Some(collection.name), &entry.id))
+        );
+        // Documents without a known date have it set to zero
+        if let Some(date) = chrono::NaiveDateTime::from_timestamp_opt(entry.date, 0).filter(|_| entry.date > 0) {
+            let _ = write!(body, "<lastmod>{}</lastmod>", date.date());
+        }
+        body.push_str("</url>\n");
+    }
+    body.push_str("</urlset>\n");
+
+    Ok(xml_response(body, config))
+}
+
+/// Allows every page to be crawled and points crawlers to the sitemap.
+pub async fn robots(req: HttpRequest, config: web::Data<Config>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .insert_header(caching::cache_control(config.cache_max_age))
+        .body(format!(
+            "User-agent: *\nAllow: /\nSitemap: {}/sitemap.xml\n",
+            base_url(&req, &config)
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::http::{StatusCode, header};
+    use actix_web::test::{TestRequest, call_service, read_body};
+
+    #[actix_rt::test]
+    async fn sitemaps_list_every_document_page() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(
+            mock,
+            Config {
+                public_base_url: Some("https://normas.unb.br/".to_string()),
+                ..Config::default()
+            }
+        );
+
+        let response = call_service(&app, TestRequest::get().uri("/sitemap.xml").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/xml; charset=utf-8"
+        );
+        assert_eq!(
+            read_body(response).await,
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+                "  <sitemap><loc>https://normas.unb.br/sitemaps/0.xml</loc></sitemap>\n",
+                "</sitemapindex>\n",
+            )
+        );
+
+        let response = call_service(&app, TestRequest::get().uri("/sitemaps/0.xml").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(read_body(response).await.to_vec()).unwrap();
+        assert!(body.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset "));
+        assert_eq!(body.matches("<url>").count(), 6);
+        assert!(body.contains(
+            "  <url><loc>https://normas.unb.br/documents/\
+             e6abb4cd2d06313a64ed60c32c6442008b3530e753cdaadfb888d5cd72184fff/view</loc>\
+             <lastmod>2019-04-01</lastmod></url>\n"
+        ));
+
+        let request = TestRequest::get().uri("/robots.txt").to_request();
+        let body = read_body(call_service(&app, request).await).await;
+        assert_eq!(
+            body,
+            "User-agent: *\nAllow: /\nSitemap: https://normas.unb.br/sitemap.xml\n"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn sitemaps_past_the_documents_are_not_found() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        for uri in [
+            "/sitemaps/1.xml".to_string(),
+            format!("/sitemaps/{}.xml", usize::MAX),
+            format!("/sitemaps/{}.xml", usize::MAX / super::SITEMAP_SIZE),
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+
+        // Without a configured address, the one of the request is used
+        let request = TestRequest::get()
+            .uri("/sitemap.xml")
+            .insert_header((header::HOST, "localhost:8080"))
+            .to_request();
+        let body = String::from_utf8(read_body(call_service(&app, request).await).await.to_vec()).unwrap();
+        assert!(body.contains("<loc>http://localhost:8080/sitemaps/0.xml</loc>"));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::api::v1;
+use crate::caching;
+use crate::config::Config;
+use crate::corpus::CorpusCache;
+use crate::resilience::Guard;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use chrono::{Datelike, NaiveDateTime};
+use meilisearch_sdk::client::Client;
+use std::collections::BTreeMap;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Counts of the indexed documents, built along with the corpus statistics.
+#[derive(Default)]
+pub struct Statistics {
+    documents: usize,
+    /// Number of documents by value of `is_normative`.
+    categories: BTreeMap<i32, usize>,
+    /// Number of documents by year of publication. Documents without a known date are left out.
+    years: BTreeMap<i32, usize>,
+    /// Total size of the extracted text of the documents, in bytes.
+    content_size: usize,
+    oldest: Option<NaiveDateTime>,
+    newest: Option<NaiveDateTime>,
+    /// Last time documents were added to, changed in or removed from any of the indexes.
+    last_update: Option<OffsetDateTime>,
+}
+
+impl Statistics {
+    /// Takes into account the last update of one of the indexes counted.
+    pub fn updated(&mut self, last_update: Option<OffsetDateTime>) {
+        self.last_update = self.last_update.max(last_update);
+    }
+
+    /// Counts a document of the index.
+    pub fn add(&mut self, date: i64, is_normative: i32, content: &str) {
+        self.documents += 1;
+        *self.categories.entry(is_normative).or_default() += 1;
+        self.content_size += content.len();
+
+        // Documents without a known date have it set to zero
+        let Some(date) = NaiveDateTime::from_timestamp_opt(date, 0).filter(|_| date > 0) else {
+            return;
+        };
+        *self.years.entry(date.year()).or_default() += 1;
+        self.oldest = Some(self.oldest.map_or(date, |oldest| oldest.min(date)));
+        self.newest = Some(self.newest.map_or(date, |newest| newest.max(date)));
+    }
+
+    /// Returns the statistics as given by the statistics endpoint.
+    pub fn summary(&self) -> v1::Statistics<'static> {
+        let mut by_category: BTreeMap<&str, usize> = BTreeMap::new();
+        for (is_normative, count) in &self.categories {
+            *by_category.entry(category_name(*is_normative)).or_default() += count;
+        }
+
+        v1::Statistics {
+            documents: self.documents,
+            by_category,
+            by_year: self.years.clone(),
+            content_size: self.content_size,
+            oldest_date: self.oldest.map(|date| date.date().to_string()),
+            newest_date: self.newest.map(|date| date.date().to_string()),
+            last_update: self
+                .last_update
+                .and_then(|last_update| last_update.format(&Rfc3339).ok()),
+        }
+    }
+}
+
+/// Returns the name of a value of `is_normative`, as used in the statistics.
+const fn category_name(is_normative: i32) -> &'static str {
+    match is_normative {
+        1 => "normative",
+        2 => "deliberative",
+        _ => "unspecified",
+    }
+}
+
+/// Returns how many documents are indexed in every collection, by category and by year of
+/// publication, how much text they hold, the range of their publication dates and when an index
+/// was last updated. The counts come from the corpus snapshot, so they can be a few minutes behind
+/// the indexes.
+pub async fn stats(
+    req: HttpRequest,
+    client: web::Data<Client>,
+    guard: web::Data<Guard>,
+    corpus: web::Data<CorpusCache>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let corpus = corpus.get(&client, &guard, &config).await?;
+
+    let body = serde_json::to_string(&corpus.statistics.summary())?;
+    Ok(caching::respond(
+        &req,
+        caching::etag(&body),
+        config.cache_max_age,
+        || HttpResponse::Ok().content_type("application/json").body(body),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::test::{TestRequest, call_service, read_body_json};
+    use serde_json::{Value, json};
+
+    #[actix_rt::test]
+    async fn stats_count_the_indexed_documents() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock);
+
+        let response = call_service(&app, TestRequest::get().uri("/api/v1/stats").to_request()).await;
+        assert!(response.status().is_success());
+        assert!(response.headers().contains_key("ETag"));
+        let body: Value = read_body_json(response).await;
+        assert_eq!(
+            body,
+            json!({
+                "documents": 6,
+                "by_category": { "deliberative": 1, "normative": 4, "unspecified": 1 },
+                "by_year": { "2019": 1, "2020": 1, "2021": 1, "2022": 1, "2023": 2 },
+                "content_size": 1092,
+                "oldest_date": "2019-04-01",
+                "newest_date": "2023-06-01",
+                "last_update": "2023-01-01T00:00:00Z",
+            })
+        );
+    }
+
+    #[actix_rt::test]
+    async fn stats_count_every_index_once() {
+        let mock = MockMeilisearch::start();
+        let mut config = Config::default();
+        config.collections.insert("atas".to_string(), "atas".to_string());
+        config
+            .collections
+            .insert("resolucoes".to_string(), "entries".to_string());
+        let app = test_app!(mock, config);
+
+        let response = call_service(&app, TestRequest::get().uri("/api/v1/stats").to_request()).await;
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["documents"], 12);
+        assert_eq!(body["content_size"], 2184);
+    }
+}
@@ -0,0 +1,101 @@
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+/// Folds a string for accent- and case-insensitive comparisons. The text is decomposed (NFD), its
+/// combining marks are dropped and the remaining characters are lowercased, so that “Progressão”
+/// and “PROGRESSAO” compare equal. Runs of whitespace, including the line breaks pdftotext leaves
+/// in the middle of sentences, are collapsed into a single space.
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    let mut pending_space = false;
+
+    for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_whitespace() {
+            pending_space = !folded.is_empty();
+            continue;
+        }
+        if pending_space {
+            folded.push(' ');
+            pending_space = false;
+        }
+        folded.extend(c.to_lowercase());
+    }
+
+    folded
+}
+
+/// Returns the beginning of a text, at most `length` characters long, with runs of whitespace
+/// collapsed into a single space. An ellipsis marks a text that was cut.
+pub fn snippet(text: &str, length: usize) -> String {
+    let mut snippet = String::new();
+    for (count, word) in text.split_whitespace().enumerate() {
+        if count > 0 {
+            snippet.push(' ');
+        }
+        snippet.push_str(word);
+        if snippet.chars().count() > length {
+            let end = snippet.char_indices().nth(length).map_or(snippet.len(), |(end, _)| end);
+            snippet.truncate(end);
+            snippet.push('…');
+            break;
+        }
+    }
+    snippet
+}
+
+/// Folds a string like [`fold`], also returning, for every byte of the folded string, the byte
+/// range of the original character it came from. This allows matches found in the folded text to
+/// be mapped back to the original text.
+fn fold_with_offsets(text: &str) -> (String, Vec<Range<usize>>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len());
+    let mut pending_space: Option<Range<usize>> = None;
+
+    for (start, c) in text.char_indices() {
+        let source = start..start + c.len_utf8();
+        if c.is_whitespace() {
+            if !folded.is_empty() && pending_space.is_none() {
+                pending_space = Some(source);
+            }
+            continue;
+        }
+        if let Some(space) = pending_space.take() {
+            folded.push(' ');
+            offsets.push(space);
+        }
+        for folded_char in c.nfd().filter(|c| !is_combining_mark(*c)).flat_map(char::to_lowercase) {
+            folded.push(folded_char);
+            offsets.extend(std::iter::repeat_n(source.clone(), folded_char.len_utf8()));
+        }
+    }
+
+    (folded, offsets)
+}
+
+/// Finds every occurrence of the given folded terms in a text, ignoring case, accents and the
+/// amount of whitespace between words. Returns the byte ranges of the matches in the original
+/// text, sorted and with overlapping matches merged.
+pub fn find_matches(text: &str, terms: &[String]) -> Vec<Range<usize>> {
+    let (folded, offsets) = fold_with_offsets(text);
+    let mut matches: Vec<Range<usize>> = terms
+        .iter()
+        .filter(|term| !term.is_empty())
+        .flat_map(|term| {
+            folded
+                .match_indices(term.as_str())
+                .map(|(start, term)| offsets[start].start..offsets[start + term.len() - 1].end)
+        })
+        .collect();
+
+    matches.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(matches.len());
+    for range in matches {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
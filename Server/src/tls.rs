@@ -0,0 +1,260 @@
+use crate::config::TlsConfig;
+use actix_web::http::header;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use log::{error, info};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// How often the certificate and key files are checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Reads a PEM encoded certificate chain and private key into a key usable by rustls.
+fn load_certified_key(tls: &TlsConfig) -> io::Result<CertifiedKey> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let certificates: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(File::open(&tls.certificate)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certificates.is_empty() {
+        return Err(invalid(format!("No certificate found in {:?}", tls.certificate)));
+    }
+
+    let key = rustls_pemfile::read_all(&mut BufReader::new(File::open(&tls.key)?))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(format!("No private key found in {:?}", tls.key)))?;
+    let signing_key = rustls::sign::any_supported_type(&key)
+        .map_err(|e| invalid(format!("Unsupported private key in {:?}: {e}", tls.key)))?;
+
+    Ok(CertifiedKey::new(certificates, signing_key))
+}
+
+/// Returns when the certificate and the key were last modified. Certificate renewals replace both.
+fn modified_at(tls: &TlsConfig) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    (modified(&tls.certificate), modified(&tls.key))
+}
+
+/// Hands the current certificate to every TLS handshake. The certificate is swapped when the files
+/// change, so connections made after a renewal use the new certificate while the open ones are
+/// left alone.
+struct CertificateResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|current| Arc::clone(&current))
+    }
+}
+
+/// Builds the rustls configuration of the HTTPS listener and starts watching the certificate files
+/// for renewals. Fails if the certificate or the key cannot be read on startup; afterwards, a
+/// renewal that cannot be read is logged and the previous certificate is kept.
+pub fn server_config(tls: TlsConfig) -> io::Result<ServerConfig> {
+    let resolver = Arc::new(CertificateResolver {
+        current: RwLock::new(Arc::new(load_certified_key(&tls)?)),
+    });
+
+    let watched = Arc::clone(&resolver);
+    actix_rt::spawn(async move {
+        let mut last_modified = modified_at(&tls);
+        let mut interval = actix_rt::time::interval(RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            let modified = modified_at(&tls);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            match load_certified_key(&tls) {
+                Ok(key) => {
+                    *watched.current.write().expect("Certificate lock poisoned.") = Arc::new(key);
+                    info!("Reloaded the TLS certificate from {:?}.", tls.certificate);
+                },
+                Err(e) => error!("Keeping the current TLS certificate: {e}"),
+            }
+        }
+    });
+
+    Ok(ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver))
+}
+
+/// Removes the port from the value of a Host header, keeping IPv6 addresses in brackets intact.
+fn host_name(host: &str) -> &str {
+    host.rsplit_once(':')
+        .filter(|(_, port)| !port.is_empty() && port.bytes().all(|c| c.is_ascii_digit()))
+        .map_or(host, |(name, _)| name)
+}
+
+/// Redirects a plain HTTP request to the same path on the HTTPS listener. The host is taken from
+/// the Host header, or the request target if it is absolute, and never from the X-Forwarded-Host or
+/// Forwarded headers, which would let a request redirect its client to any host.
+async fn redirect(req: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().host());
+    let Some(host) = host else {
+        return HttpResponse::BadRequest().body("Missing Host header");
+    };
+    let port = match **https_port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    let location = format!("https://{}{port}{path}", host_name(host));
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+/// Runs a plain HTTP listener on `address` that only redirects requests to the HTTPS listener on
+/// `https_port`.
+pub async fn run_redirect(address: &str, https_port: u16, shutdown_timeout: u64) -> io::Result<()> {
+    let https_port = web::Data::new(https_port);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(https_port.clone())
+            .default_service(web::to(redirect))
+    })
+    .workers(1)
+    .shutdown_timeout(shutdown_timeout)
+    .bind(address)?;
+
+    info!("Redirecting http://{address} to HTTPS");
+    server.run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use std::path::PathBuf;
+
+    /// Returns a path in the self-signed certificate fixtures, made for `localhost` and only used
+    /// by the tests.
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/tls").join(name)
+    }
+
+    fn tls(certificate: PathBuf, key: PathBuf) -> TlsConfig {
+        TlsConfig {
+            certificate,
+            key,
+            redirect_address: None,
+        }
+    }
+
+    #[test]
+    fn test_load_certified_key() {
+        for key in ["key.pem", "key-sec1.pem"] {
+            let certified_key = load_certified_key(&tls(fixture("certificate.pem"), fixture(key))).unwrap();
+            assert_eq!(certified_key.cert.len(), 1, "{key}");
+            assert_eq!(
+                certified_key.cert[0].0,
+                rustls_pemfile::certs(&mut BufReader::new(File::open(fixture("certificate.pem")).unwrap())).unwrap()[0]
+            );
+        }
+
+        let error = |certificate, key| {
+            load_certified_key(&tls(fixture(certificate), fixture(key)))
+                .err()
+                .unwrap()
+        };
+
+        let swapped = error("key.pem", "key.pem");
+        assert_eq!(swapped.kind(), io::ErrorKind::InvalidData);
+        assert!(swapped.to_string().starts_with("No certificate found"));
+
+        let swapped = error("certificate.pem", "certificate.pem");
+        assert_eq!(swapped.kind(), io::ErrorKind::InvalidData);
+        assert!(swapped.to_string().starts_with("No private key found"));
+
+        assert_eq!(error("missing.pem", "key.pem").kind(), io::ErrorKind::NotFound);
+    }
+
+    #[actix_rt::test]
+    async fn server_config_serves_the_certificate() {
+        assert!(server_config(tls(fixture("certificate.pem"), fixture("key.pem"))).is_ok());
+        assert!(server_config(tls(fixture("certificate.pem"), fixture("missing.pem"))).is_err());
+    }
+
+    #[test]
+    fn test_host_name() {
+        assert_eq!(host_name("mds.unb.br"), "mds.unb.br");
+        assert_eq!(host_name("mds.unb.br:8080"), "mds.unb.br");
+        assert_eq!(host_name("127.0.0.1:80"), "127.0.0.1");
+        assert_eq!(host_name("[::1]:8080"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+        assert_eq!(host_name("mds.unb.br:"), "mds.unb.br:");
+    }
+
+    #[actix_rt::test]
+    async fn plain_requests_are_redirected_to_https() {
+        for (https_port, host, location) in [
+            (443_u16, "mds.unb.br", "https://mds.unb.br/buscar?q=f%C3%A9rias"),
+            (443, "mds.unb.br:80", "https://mds.unb.br/buscar?q=f%C3%A9rias"),
+            (8443, "[::1]:8080", "https://[::1]:8443/buscar?q=f%C3%A9rias"),
+        ] {
+            let app = init_service(
+                App::new()
+                    .app_data(web::Data::new(https_port))
+                    .default_service(web::to(redirect)),
+            )
+            .await;
+            let request = TestRequest::get()
+                .uri("/buscar?q=f%C3%A9rias")
+                .insert_header((header::HOST, host))
+                .to_request();
+            let response = call_service(&app, request).await;
+            assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+            assert_eq!(response.headers().get(header::LOCATION).unwrap(), location);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn forwarded_hosts_are_not_redirected_to() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(443_u16))
+                .default_service(web::to(redirect)),
+        )
+        .await;
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header((header::HOST, "mds.unb.br"))
+            .insert_header(("x-forwarded-host", "evil.example.org"))
+            .insert_header((header::FORWARDED, "host=evil.example.org"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers().get(header::LOCATION).unwrap(), "https://mds.unb.br/");
+
+        let request = TestRequest::get()
+            .uri("/")
+            .insert_header(("x-forwarded-host", "evil.example.org"))
+            .to_request();
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
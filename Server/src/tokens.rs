@@ -0,0 +1,224 @@
+use crate::admin::bearer_identity;
+use crate::api::v1::TenantToken;
+use crate::collections;
+use crate::config::Config;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use chrono::Utc;
+use log::{error, info};
+use meilisearch_sdk::client::Client;
+use serde::Deserialize;
+use serde_json::json;
+use time::OffsetDateTime;
+
+/// A request for a tenant token.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TokenRequest {
+    /// Collection the token gives access to, the default collection if left out.
+    collection: Option<String>,
+    /// Meilisearch filter applied to every search made with the token, e.g. `is_normative = 1`.
+    filter: Option<String>,
+    /// Lifetime of the token in seconds, at most the configured maximum.
+    ttl: Option<u64>,
+}
+
+/// Issues a short-lived Meilisearch tenant token, so that a trusted frontend can search one
+/// collection directly instead of going through the server. The token only allows searching the
+/// index of the collection, restricted by the optional filter, which Meilisearch adds to every
+/// search made with it. Frontends are identified by their bearer token.
+pub async fn issue(
+    req: HttpRequest,
+    request: web::Json<TokenRequest>,
+    client: web::Data<Client>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    let tenant_tokens = &config.tenant_tokens;
+    let frontend = bearer_identity(&req, &tenant_tokens.clients, "Invalid client token")?;
+    let Some(api_key_uid) = &tenant_tokens.api_key_uid else {
+        return Err(actix_web::error::ErrorServiceUnavailable(
+            "Tenant tokens are not configured",
+        ));
+    };
+
+    let index = match collections::resolve(&config, request.collection.as_deref())?.as_slice() {
+        [collection] => collection.index.to_string(),
+        _ => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "A token gives access to a single collection",
+            ));
+        },
+    };
+    let rules = match request.filter.as_deref().map(str::trim) {
+        Some(filter) if !filter.is_empty() => json!({ "filter": filter }),
+        _ => json!({}),
+    };
+
+    let ttl = request
+        .ttl
+        .unwrap_or(tenant_tokens.max_ttl)
+        .clamp(1, tenant_tokens.max_ttl.max(1));
+    let expires_at = Utc::now() + chrono::Duration::seconds(i64::try_from(ttl).unwrap_or(i64::MAX));
+    let token = OffsetDateTime::from_unix_timestamp(expires_at.timestamp())
+        .map_err(|e| e.to_string())
+        .and_then(|expiry| {
+            // The tokens are signed with a key allowed to search, rather than the key of the server
+            let signing_key = std::env::var("MEILISEARCH_TENANT_KEY").ok();
+            client
+                .generate_tenant_token(
+                    api_key_uid.clone(),
+                    json!({ index.as_str(): rules }),
+                    signing_key.as_deref(),
+                    Some(expiry),
+                )
+                .map_err(|e| e.to_string())
+        })
+        .map_err(|e| {
+            error!("Could not generate a tenant token: {e}");
+            actix_web::error::ErrorInternalServerError("Could not generate the token")
+        })?;
+
+    info!("Issued a tenant token for {index} to {frontend}, valid for {ttl} seconds.");
+
+    Ok(HttpResponse::Ok().json(TenantToken {
+        token,
+        index: &index,
+        meilisearch_url: tenant_tokens.public_url.as_deref().unwrap_or(&config.meilisearch_url),
+        expires_at: expires_at.to_rfc3339(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, TenantTokens};
+    use crate::mock_meilisearch::{MockMeilisearch, test_app};
+    use actix_web::http::{StatusCode, header};
+    use actix_web::test::{TestRequest, call_service, read_body_json};
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use chrono::Utc;
+    use serde_json::{Value, json};
+    use std::collections::HashMap;
+
+    /// Uid of the key the tokens are signed with, which Meilisearch requires to be a UUID v4.
+    const KEY_UID: &str = "76cf8b87-fd12-4688-ad34-260d930ca4f4";
+
+    fn config(api_key_uid: Option<&str>) -> Config {
+        Config {
+            tenant_tokens: TenantTokens {
+                clients: HashMap::from([("frontend-token".to_string(), "frontend".to_string())]),
+                api_key_uid: api_key_uid.map(str::to_string),
+                max_ttl: 600,
+                public_url: Some("https://search.example.org".to_string()),
+            },
+            ..Config::default()
+        }
+    }
+
+    fn request(token: Option<&str>, body: Value) -> TestRequest {
+        let request = TestRequest::post().uri("/api/v1/tokens").set_json(body);
+        match token {
+            Some(token) => request.insert_header((header::AUTHORIZATION, format!("Bearer {token}"))),
+            None => request,
+        }
+    }
+
+    /// Returns the claims of a JSON web token, without checking its signature.
+    fn token_claims(token: &str) -> Value {
+        let payload = token.split('.').nth(1).unwrap();
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn token_is_restricted_to_the_index_and_filter() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock, config(Some(KEY_UID)));
+
+        let issued_at = Utc::now().timestamp();
+        let response = call_service(
+            &app,
+            request(
+                Some("frontend-token"),
+                json!({ "filter": "is_normative = 1", "ttl": 60 }),
+            )
+            .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["index"], "entries");
+        assert_eq!(body["meilisearch_url"], "https://search.example.org");
+
+        let claims = token_claims(body["token"].as_str().unwrap());
+        assert_eq!(claims["apiKeyUid"], KEY_UID);
+        assert_eq!(
+            claims["searchRules"],
+            json!({ "entries": { "filter": "is_normative = 1" } })
+        );
+        let expiry = claims["exp"].as_i64().unwrap();
+        assert!((issued_at + 60..=issued_at + 61).contains(&expiry));
+        assert_eq!(
+            chrono::DateTime::parse_from_rfc3339(body["expires_at"].as_str().unwrap())
+                .unwrap()
+                .timestamp(),
+            expiry
+        );
+
+        // Without a filter the index can be searched whole, for at most the configured lifetime
+        let response = call_service(
+            &app,
+            request(Some("frontend-token"), json!({ "filter": " ", "ttl": 100_000 })).to_request(),
+        )
+        .await;
+        let body: Value = read_body_json(response).await;
+        let claims = token_claims(body["token"].as_str().unwrap());
+        assert_eq!(claims["searchRules"], json!({ "entries": {} }));
+        assert!(claims["exp"].as_i64().unwrap() <= Utc::now().timestamp() + 600);
+    }
+
+    #[actix_rt::test]
+    async fn tokens_are_issued_outside_the_versioned_api() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock, config(Some(KEY_UID)));
+
+        let issue = request(Some("frontend-token"), json!({ "filter": "is_normative = 1" })).uri("/api/tokens");
+        let response = call_service(&app, issue.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("deprecation").is_none());
+        let body: Value = read_body_json(response).await;
+        let claims = token_claims(body["token"].as_str().unwrap());
+        assert_eq!(
+            claims["searchRules"],
+            json!({ "entries": { "filter": "is_normative = 1" } })
+        );
+
+        let anonymous = request(None, json!({})).uri("/api/tokens");
+        let response = call_service(&app, anonymous.to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn token_requests_are_rejected() {
+        let mock = MockMeilisearch::start();
+        let app = test_app!(mock, config(Some(KEY_UID)));
+
+        let cases = [
+            (request(None, json!({})), StatusCode::UNAUTHORIZED),
+            (request(Some("wrong"), json!({})), StatusCode::UNAUTHORIZED),
+            (
+                request(Some("frontend-token"), json!({ "collection": "unknown" })),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                request(Some("frontend-token"), json!({ "indexes": ["entries"] })),
+                StatusCode::BAD_REQUEST,
+            ),
+        ];
+        for (request, status) in cases {
+            assert_eq!(call_service(&app, request.to_request()).await.status(), status);
+        }
+
+        let app = test_app!(mock, config(None));
+        let response = call_service(&app, request(Some("frontend-token"), json!({})).to_request()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
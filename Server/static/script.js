@@ -1,5 +1,11 @@
 // Add an event listener to the input field to handle user input
 document.querySelector('#searchQuery').addEventListener('input', () => {
+    // The live connection cancels superseded queries on the server, so each update is sent as is
+    if (liveSocket !== null && liveSocket.readyState === WebSocket.OPEN) {
+        liveSearch();
+        return;
+    }
+
     // Delay the search by a short time to allow the user to finish typing
     clearTimeout(timeout);
     timeout = setTimeout(performSearch, 300); // Adjust the delay time as needed
@@ -19,6 +25,54 @@ let searchResults = [];
 let originalResults = [];
 let filteredResults = [];
 
+// Id of the current search, sent back to the server when a result is clicked
+let searchEvent = null;
+
+// Live search connection, or null when WebSockets are unavailable and searches go through fetch
+let liveSocket = null;
+
+function connectLiveSearch() {
+    if (!('WebSocket' in window)) {
+        return;
+    }
+
+    const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+    const socket = new WebSocket(`${protocol}//${window.location.host}/api/v1/search/live`);
+
+    socket.addEventListener('open', () => {
+        liveSocket = socket;
+    });
+    socket.addEventListener('message', (message) => {
+        const data = JSON.parse(message.data);
+
+        // Ignore results of a query the input no longer shows
+        if (data.q !== document.querySelector('#searchQuery').value) {
+            return;
+        }
+
+        if (data.error !== undefined) {
+            console.error('Error:', data.error);
+            document.querySelector('#results').innerHTML = 'An error occurred.';
+            return;
+        }
+
+        searchEvent = data.event === null ? null : String(data.event);
+        showResults(data.results);
+    });
+    socket.addEventListener('close', () => {
+        // Fall back to fetch until the connection is back
+        liveSocket = null;
+        setTimeout(connectLiveSearch, 5000);
+    });
+}
+
+function liveSearch() {
+    const searchQuery = document.querySelector('#searchQuery').value;
+    liveSocket.send(JSON.stringify({ q: searchQuery }));
+}
+
+connectLiveSearch();
+
 function sortResults() {
     // Get the selected sort option
     const sortOption = document.querySelector('#sortSelector').value;
@@ -80,23 +134,28 @@ function performSearch() {
     resultsContainer.appendChild(loadingIndicator);
 
     // Send a GET request to your Actix backend
-    fetch(`/search?q=${encodeURIComponent(searchQuery)}`)
-        .then((response) => response.json())
-        .then((data) => {
-            // Store the search results and the original order
-            searchResults = data.results;
-            originalResults = [...searchResults];
-            filteredResults = [...searchResults];
-
-            // Display the results
-            displayResults();
+    fetch(`/api/v1/search?q=${encodeURIComponent(searchQuery)}`)
+        .then((response) => {
+            searchEvent = response.headers.get('X-Search-Event');
+            return response.json();
         })
+        .then((data) => showResults(data.results))
         .catch((error) => {
             console.error('Error:', error);
             resultsContainer.innerHTML = 'An error occurred.';
         });
 }
 
+function showResults(results) {
+    // Store the search results and the original order
+    searchResults = results;
+    originalResults = [...searchResults];
+    filteredResults = [...searchResults];
+
+    // Display the results
+    displayResults();
+}
+
 function displayResults() {
     const resultsContainer = document.querySelector('#results');
 
@@ -104,7 +163,7 @@ function displayResults() {
     resultsContainer.innerHTML = '';
 
     // Loop through the filtered results and create HTML elements to display each entry
-    filteredResults.forEach((entry) => {
+    filteredResults.forEach((entry, index) => {
         const pdfElement = document.createElement('div');
         pdfElement.classList.add('entry');
 
@@ -112,6 +171,7 @@ function displayResults() {
         const linkElement = document.createElement('a');
         linkElement.href = entry.link;
         linkElement.textContent = "View PDF";
+        linkElement.addEventListener('click', () => reportClick(entry, index + 1));
 
         const titleElement = document.createElement('h2');
         titleElement.textContent = entry.title;
@@ -134,3 +194,13 @@ function displayResults() {
         resultsContainer.appendChild(pdfElement);
     });
 }
+
+function reportClick(entry, rank) {
+    if (searchEvent === null) {
+        return;
+    }
+
+    // sendBeacon survives the page being left for the PDF
+    const click = JSON.stringify({ event: Number(searchEvent), document: entry.id, rank });
+    navigator.sendBeacon('/api/v1/analytics/click', new Blob([click], { type: 'application/json' }));
+}